@@ -0,0 +1,102 @@
+use super::{linked_list::GrowableHeap, HeapInfo};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr,
+};
+use spin::mutex::Mutex;
+
+/// Block sizes of the size classes. Each size is also the block's alignment, so they all have
+/// to be powers of two.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// Slab-style allocator that serves small allocations from per-size-class free lists and falls
+/// back to the [`GrowableHeap`] for anything larger than the biggest class.
+pub struct FixedSizeBlockAllocator {
+    list_heads: Mutex<[Option<&'static mut ListNode>; BLOCK_SIZES.len()]>,
+    fallback: GrowableHeap,
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: Mutex::new([EMPTY; BLOCK_SIZES.len()]),
+            fallback: GrowableHeap::empty(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `heap_bottom..heap_bottom + heap_size` must be mapped and unused. Must only be called once.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        self.fallback.init(heap_bottom, heap_size);
+    }
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapInfo for FixedSizeBlockAllocator {
+    fn size(&self) -> usize {
+        self.fallback.size()
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.fallback.largest_free_block()
+    }
+}
+
+/// Index of the smallest size class that fits `layout`, if any.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+unsafe impl GlobalAlloc for FixedSizeBlockAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let index = match list_index(&layout) {
+            Some(index) => index,
+            None => return self.fallback.alloc(layout),
+        };
+
+        let mut list_heads = self.list_heads.lock();
+        if let Some(node) = list_heads[index].take() {
+            list_heads[index] = node.next.take();
+            return node as *mut ListNode as *mut u8;
+        }
+        drop(list_heads);
+
+        // No free block in this class yet, carve a new one out of the fallback heap
+        let block_size = BLOCK_SIZES[index];
+        let block_align = block_size;
+        match Layout::from_size_align(block_size, block_align) {
+            Ok(layout) => self.fallback.alloc(layout),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let index = match list_index(&layout) {
+            Some(index) => index,
+            None => return self.fallback.dealloc(ptr, layout),
+        };
+
+        // Blocks are never handed back to the fallback heap, only recycled within their class
+        assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+        assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+        let mut list_heads = self.list_heads.lock();
+        let new_node = ListNode {
+            next: list_heads[index].take(),
+        };
+        let new_node_ptr = ptr as *mut ListNode;
+        new_node_ptr.write(new_node);
+        list_heads[index] = Some(&mut *new_node_ptr);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{HeapInfo, HEAP_GROW_STEP, HEAP_MAX_SIZE, HEAP_PAGE_FLAGS};
+use crate::memory::vmm;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+};
+use linked_list_allocator::Heap;
+use spin::mutex::Mutex;
+use x86_64::{
+    structures::paging::{PageSize, Size4KiB},
+    VirtAddr,
+};
+
+/// [`linked_list_allocator::Heap`] that maps more pages behind its current top whenever an
+/// allocation doesn't fit, until [`HEAP_MAX_SIZE`] is reached.
+pub struct GrowableHeap {
+    heap: Mutex<Heap>,
+}
+
+impl GrowableHeap {
+    pub const fn empty() -> Self {
+        GrowableHeap {
+            heap: Mutex::new(Heap::empty()),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `heap_bottom..heap_bottom + heap_size` must be mapped and unused. Must only be called once.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        self.heap.lock().init(heap_bottom, heap_size);
+    }
+
+    /// Tries to extend `heap` by at least `min_size` bytes.
+    fn grow(heap: &mut Heap, min_size: usize) -> bool {
+        let top = heap.top() as usize;
+        let remaining = heap.bottom() as usize + HEAP_MAX_SIZE - top;
+        let by = min_size
+            .max(HEAP_GROW_STEP)
+            .next_multiple_of(Size4KiB::SIZE as usize)
+            .min(remaining);
+        if by < min_size
+            || vmm::map_pages(VirtAddr::new(top as u64), by as u64, HEAP_PAGE_FLAGS).is_err()
+        {
+            return false;
+        }
+
+        unsafe { heap.extend(by) };
+        true
+    }
+}
+
+impl HeapInfo for GrowableHeap {
+    fn size(&self) -> usize {
+        self.heap.lock().size()
+    }
+
+    /// The hole list isn't exposed by [`Heap`], so this binary searches for the largest trial
+    /// allocation that succeeds and immediately frees it again.
+    fn largest_free_block(&self) -> usize {
+        let mut heap = self.heap.lock();
+        let (mut low, mut high) = (0, heap.free());
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            let layout = Layout::from_size_align(mid, 1).unwrap();
+            match heap.allocate_first_fit(layout) {
+                Ok(ptr) => {
+                    unsafe { heap.deallocate(ptr, layout) };
+                    low = mid;
+                }
+                Err(()) => high = mid - 1,
+            }
+        }
+        low
+    }
+}
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // Worst case the new block has to be aligned inside the freshly mapped range
+        if !Self::grow(&mut heap, layout.size() + layout.align()) {
+            return ptr::null_mut();
+        }
+        heap.allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
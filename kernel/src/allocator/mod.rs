@@ -0,0 +1,59 @@
+use crate::memory::vmm::{self, Backing, VmmError};
+use x86_64::structures::paging::PageTableFlags;
+
+pub mod fixed_size_block;
+pub mod linked_list;
+pub mod stats;
+
+#[cfg(feature = "fixed-size-block")]
+use fixed_size_block::FixedSizeBlockAllocator;
+#[cfg(not(feature = "fixed-size-block"))]
+use linked_list::GrowableHeap;
+use stats::{HeapStats, TrackedAllocator};
+
+/// Size of the heap mapped at boot.
+pub const HEAP_SIZE: usize = 1000 * 8192;
+/// Upper bound the heap may grow to when it runs out of memory.
+pub const HEAP_MAX_SIZE: usize = 256 * 1024 * 1024;
+/// Minimum number of bytes the heap is extended by at a time.
+pub const HEAP_GROW_STEP: usize = 1024 * 1024;
+
+const HEAP_PAGE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// Information the heap backends provide for [`stats`].
+pub trait HeapInfo {
+    /// Total number of bytes currently backing the heap.
+    fn size(&self) -> usize;
+    /// Size of the largest allocation that would currently succeed without growing the heap.
+    fn largest_free_block(&self) -> usize;
+}
+
+pub fn init_heap() -> Result<(), VmmError> {
+    // Reserve the address space for the whole heap up front so it can grow in place. Growing
+    // maps pages right away, so running out of frames is an allocation failure and not a fault.
+    let region = vmm::allocate_region(HEAP_MAX_SIZE as u64, 1, Backing::Manual, HEAP_PAGE_FLAGS)?;
+    vmm::map_pages(region.start(), HEAP_SIZE as u64, HEAP_PAGE_FLAGS)?;
+
+    unsafe {
+        ALLOCATOR
+            .inner()
+            .init(region.start().as_mut_ptr(), HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.stats()
+}
+
+#[cfg(feature = "fixed-size-block")]
+#[global_allocator]
+static ALLOCATOR: TrackedAllocator<FixedSizeBlockAllocator> =
+    TrackedAllocator::new(FixedSizeBlockAllocator::new());
+
+#[cfg(not(feature = "fixed-size-block"))]
+#[global_allocator]
+static ALLOCATOR: TrackedAllocator<GrowableHeap> = TrackedAllocator::new(GrowableHeap::empty());
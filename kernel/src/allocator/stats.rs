@@ -0,0 +1,126 @@
+use super::HeapInfo;
+use crate::{framebuffer, serial, serial_println, task};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+/// Snapshot of the kernel heap accounting.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub heap_size: usize,
+    pub in_use: usize,
+    pub peak: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failed: usize,
+    pub largest_free_block: usize,
+}
+
+/// Wraps a global allocator and keeps track of how it is used.
+pub struct TrackedAllocator<A> {
+    inner: A,
+    in_use: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl<A: HeapInfo> TrackedAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        TrackedAllocator {
+            inner,
+            in_use: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            heap_size: self.inner.size(),
+            in_use: self.in_use.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            largest_free_block: self.inner.largest_free_block(),
+        }
+    }
+}
+
+impl<A: GlobalAlloc + HeapInfo> TrackedAllocator<A> {
+    /// Reports a failed allocation on serial and, once it is up, the framebuffer.
+    ///
+    /// The allocator still returns null afterwards, which ends up in the panic handler through
+    /// `handle_alloc_error` for all infallible allocations.
+    fn report_failure(&self, layout: Layout) {
+        let stats = self.stats();
+        let task = task::current_task_id();
+        print_failure(serial::_print, &layout, &stats, task);
+        if framebuffer::FBWRITER.get().is_some() {
+            print_failure(framebuffer::_print, &layout, &stats, task);
+        }
+    }
+}
+
+fn print_failure(print: fn(fmt::Arguments), layout: &Layout, stats: &HeapStats, task: Option<u64>) {
+    print(format_args!(
+        "[ALLOC] failed to allocate {} bytes (align {}), task: {:?}\n",
+        layout.size(),
+        layout.align(),
+        task
+    ));
+    print(format_args!(
+        "[ALLOC] heap: {} bytes mapped, {} in use, peak {}, largest free block {}\n",
+        stats.heap_size, stats.in_use, stats.peak, stats.largest_free_block
+    ));
+    print(format_args!(
+        "[ALLOC] {} allocations, {} deallocations, {} failed\n",
+        stats.allocations, stats.deallocations, stats.failed
+    ));
+}
+
+/// Logs every allocation and deallocation to serial while enabled.
+pub fn set_tracing(enabled: bool) {
+    TRACING.store(enabled, Ordering::Relaxed);
+}
+
+unsafe impl<A: GlobalAlloc + HeapInfo> GlobalAlloc for TrackedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if ptr.is_null() {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            self.report_failure(layout);
+        } else {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            let in_use = self.in_use.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(in_use, Ordering::Relaxed);
+        }
+
+        if TRACING.load(Ordering::Relaxed) {
+            serial_println!("[ALLOC] {:?} -> {:p}", layout, ptr);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.in_use.fetch_sub(layout.size(), Ordering::Relaxed);
+
+        if TRACING.load(Ordering::Relaxed) {
+            serial_println!("[FREE] {:?} <- {:p}", layout, ptr);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use super::cpuid;
+use core::{arch::x86_64::CpuidResult, fmt};
+use spin::lazy::Lazy;
+
+static FEATURES: Lazy<Features> = Lazy::new(Features::detect);
+
+/// CPU features other modules may want to branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Fpu,
+    Tsc,
+    Msr,
+    Apic,
+    Pge,
+    Pat,
+    Fxsr,
+    Sse,
+    Sse2,
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Pcid,
+    X2Apic,
+    TscDeadline,
+    Xsave,
+    Avx,
+    Rdrand,
+    Hypervisor,
+    FsGsBase,
+    Smep,
+    Avx2,
+    Invpcid,
+    Rdseed,
+    Smap,
+    Umip,
+    Nx,
+    Page1Gb,
+    Rdtscp,
+    InvariantTsc,
+}
+
+/// Register a feature bit is reported in.
+#[derive(Clone, Copy)]
+enum Reg {
+    Leaf1Ecx,
+    Leaf1Edx,
+    Leaf7Ebx,
+    Leaf7Ecx,
+    Ext1Edx,
+    Ext7Edx,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 31] = [
+        Feature::Fpu,
+        Feature::Tsc,
+        Feature::Msr,
+        Feature::Apic,
+        Feature::Pge,
+        Feature::Pat,
+        Feature::Fxsr,
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::Sse3,
+        Feature::Ssse3,
+        Feature::Sse41,
+        Feature::Sse42,
+        Feature::Pcid,
+        Feature::X2Apic,
+        Feature::TscDeadline,
+        Feature::Xsave,
+        Feature::Avx,
+        Feature::Rdrand,
+        Feature::Hypervisor,
+        Feature::FsGsBase,
+        Feature::Smep,
+        Feature::Avx2,
+        Feature::Invpcid,
+        Feature::Rdseed,
+        Feature::Smap,
+        Feature::Umip,
+        Feature::Nx,
+        Feature::Page1Gb,
+        Feature::Rdtscp,
+        Feature::InvariantTsc,
+    ];
+
+    /// Where CPUID reports the feature.
+    fn location(self) -> (Reg, u32) {
+        match self {
+            Feature::Fpu => (Reg::Leaf1Edx, 0),
+            Feature::Tsc => (Reg::Leaf1Edx, 4),
+            Feature::Msr => (Reg::Leaf1Edx, 5),
+            Feature::Apic => (Reg::Leaf1Edx, 9),
+            Feature::Pge => (Reg::Leaf1Edx, 13),
+            Feature::Pat => (Reg::Leaf1Edx, 16),
+            Feature::Fxsr => (Reg::Leaf1Edx, 24),
+            Feature::Sse => (Reg::Leaf1Edx, 25),
+            Feature::Sse2 => (Reg::Leaf1Edx, 26),
+            Feature::Sse3 => (Reg::Leaf1Ecx, 0),
+            Feature::Ssse3 => (Reg::Leaf1Ecx, 9),
+            Feature::Sse41 => (Reg::Leaf1Ecx, 19),
+            Feature::Sse42 => (Reg::Leaf1Ecx, 20),
+            Feature::Pcid => (Reg::Leaf1Ecx, 17),
+            Feature::X2Apic => (Reg::Leaf1Ecx, 21),
+            Feature::TscDeadline => (Reg::Leaf1Ecx, 24),
+            Feature::Xsave => (Reg::Leaf1Ecx, 26),
+            Feature::Avx => (Reg::Leaf1Ecx, 28),
+            Feature::Rdrand => (Reg::Leaf1Ecx, 30),
+            Feature::Hypervisor => (Reg::Leaf1Ecx, 31),
+            Feature::FsGsBase => (Reg::Leaf7Ebx, 0),
+            Feature::Smep => (Reg::Leaf7Ebx, 7),
+            Feature::Avx2 => (Reg::Leaf7Ebx, 5),
+            Feature::Invpcid => (Reg::Leaf7Ebx, 10),
+            Feature::Rdseed => (Reg::Leaf7Ebx, 18),
+            Feature::Smap => (Reg::Leaf7Ebx, 20),
+            Feature::Umip => (Reg::Leaf7Ecx, 2),
+            Feature::Nx => (Reg::Ext1Edx, 20),
+            Feature::Page1Gb => (Reg::Ext1Edx, 26),
+            Feature::Rdtscp => (Reg::Ext1Edx, 27),
+            Feature::InvariantTsc => (Reg::Ext7Edx, 8),
+        }
+    }
+
+    /// Name as used in `/proc/cpuinfo`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Fpu => "fpu",
+            Feature::Tsc => "tsc",
+            Feature::Msr => "msr",
+            Feature::Apic => "apic",
+            Feature::Pge => "pge",
+            Feature::Pat => "pat",
+            Feature::Fxsr => "fxsr",
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Sse3 => "pni",
+            Feature::Ssse3 => "ssse3",
+            Feature::Sse41 => "sse4_1",
+            Feature::Sse42 => "sse4_2",
+            Feature::Pcid => "pcid",
+            Feature::X2Apic => "x2apic",
+            Feature::TscDeadline => "tsc_deadline_timer",
+            Feature::Xsave => "xsave",
+            Feature::Avx => "avx",
+            Feature::Rdrand => "rdrand",
+            Feature::Hypervisor => "hypervisor",
+            Feature::FsGsBase => "fsgsbase",
+            Feature::Smep => "smep",
+            Feature::Avx2 => "avx2",
+            Feature::Invpcid => "invpcid",
+            Feature::Rdseed => "rdseed",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::Nx => "nx",
+            Feature::Page1Gb => "pdpe1gb",
+            Feature::Rdtscp => "rdtscp",
+            Feature::InvariantTsc => "invariant_tsc",
+        }
+    }
+}
+
+/// The raw CPUID feature words, read once at boot.
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+    leaf1_ecx: u32,
+    leaf1_edx: u32,
+    leaf7_ebx: u32,
+    leaf7_ecx: u32,
+    ext1_edx: u32,
+    ext7_edx: u32,
+}
+
+impl Features {
+    fn detect() -> Self {
+        let max_leaf = cpuid(0, 0).eax;
+        let max_ext_leaf = cpuid(0x8000_0000, 0).eax;
+        let leaf = |leaf: u32, max: u32| {
+            if leaf <= max {
+                cpuid(leaf, 0)
+            } else {
+                CpuidResult {
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                }
+            }
+        };
+
+        let leaf1 = leaf(1, max_leaf);
+        let leaf7 = leaf(7, max_leaf);
+        let ext1 = leaf(0x8000_0001, max_ext_leaf);
+        let ext7 = leaf(0x8000_0007, max_ext_leaf);
+        Features {
+            leaf1_ecx: leaf1.ecx,
+            leaf1_edx: leaf1.edx,
+            leaf7_ebx: leaf7.ebx,
+            leaf7_ecx: leaf7.ecx,
+            ext1_edx: ext1.edx,
+            ext7_edx: ext7.edx,
+        }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        let (reg, bit) = feature.location();
+        let word = match reg {
+            Reg::Leaf1Ecx => self.leaf1_ecx,
+            Reg::Leaf1Edx => self.leaf1_edx,
+            Reg::Leaf7Ebx => self.leaf7_ebx,
+            Reg::Leaf7Ecx => self.leaf7_ecx,
+            Reg::Ext1Edx => self.ext1_edx,
+            Reg::Ext7Edx => self.ext7_edx,
+        };
+        word & (1 << bit) != 0
+    }
+
+    /// Iterates over all features the CPU reports.
+    pub fn iter(&self) -> impl Iterator<Item = Feature> + '_ {
+        Feature::ALL
+            .into_iter()
+            .filter(|&feature| self.has(feature))
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, feature) in self.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(feature.name())?;
+        }
+        Ok(())
+    }
+}
+
+/// Features of the boot CPU.
+pub fn get() -> &'static Features {
+    &FEATURES
+}
+
+/// Shorthand for `get().has(feature)`.
+pub fn has(feature: Feature) -> bool {
+    FEATURES.has(feature)
+}
@@ -0,0 +1,139 @@
+use super::{
+    cpuid,
+    features::{self, Feature},
+};
+use crate::serial_println;
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use core::{
+    alloc::Layout,
+    arch::asm,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use x86_64::registers::{
+    control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+    xcontrol::{XCr0, XCr0Flags},
+};
+
+/// Size of the legacy `fxsave` area.
+const FXSAVE_AREA_SIZE: usize = 512;
+const SAVE_AREA_ALIGN: usize = 64;
+/// x87 control word after `fninit`: all exceptions masked, extended precision.
+const DEFAULT_FCW: u16 = 0x037F;
+/// SSE control/status after reset: all exceptions masked, round to nearest.
+const DEFAULT_MXCSR: u32 = 0x1F80;
+
+/// Size of the save area [`FpuState`] needs, 0 until [`init`] ran.
+static SAVE_AREA_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Enables the x87 FPU, SSE and, if available, XSAVE and AVX for the current CPU.
+pub fn init() {
+    let features = features::get();
+
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+
+    let area_size = if features.has(Feature::Xsave) {
+        let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+        if features.has(Feature::Avx) {
+            xcr0 |= XCr0Flags::AVX;
+        }
+        unsafe {
+            Cr4::update(|flags| flags.insert(Cr4Flags::OSXSAVE));
+            XCr0::write(xcr0);
+        }
+        // EBX reports the size needed for the components enabled in XCR0 right now
+        cpuid(0xD, 0).ebx as usize
+    } else {
+        FXSAVE_AREA_SIZE
+    };
+    SAVE_AREA_SIZE.store(area_size, Ordering::Relaxed);
+
+    unsafe { asm!("fninit", options(nomem, nostack)) };
+    serial_println!("[FPU] initialized, {} byte save area", area_size);
+}
+
+/// Saved FPU, SSE and AVX register state of one context.
+///
+/// Uses `xsave`/`xrstor` when the CPU supports it and falls back to `fxsave`/`fxrstor`.
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+impl FpuState {
+    /// A state equivalent to a freshly initialized FPU.
+    pub fn new() -> Self {
+        let size = SAVE_AREA_SIZE.load(Ordering::Relaxed);
+        assert!(size != 0, "FPU not initialized");
+
+        let layout = Layout::from_size_align(size, SAVE_AREA_ALIGN).unwrap();
+        let Some(area) = NonNull::new(unsafe { alloc_zeroed(layout) }) else {
+            handle_alloc_error(layout);
+        };
+        // The legacy region is shared by both formats, with xsave an all-zero header means
+        // every other component starts out in its initial configuration
+        unsafe {
+            area.cast::<u16>().write(DEFAULT_FCW);
+            area.add(24).cast::<u32>().write(DEFAULT_MXCSR);
+        }
+        FpuState { area, layout }
+    }
+
+    /// Stores the current register state of this CPU.
+    pub fn save(&mut self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if features::has(Feature::Xsave) {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) area,
+                    in("eax") u32::MAX,
+                    in("edx") u32::MAX,
+                    options(nostack),
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+            }
+        }
+    }
+
+    /// Loads the saved state into the registers of this CPU.
+    ///
+    /// # Safety
+    ///
+    /// Overwrites the FPU state of whatever is currently running on the CPU.
+    pub unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+        if features::has(Feature::Xsave) {
+            asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") u32::MAX,
+                in("edx") u32::MAX,
+                options(nostack, readonly),
+            );
+        } else {
+            asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly));
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
+
+unsafe impl Send for FpuState {}
@@ -0,0 +1,182 @@
+use super::{
+    cpuid,
+    features::{self, Features},
+};
+use core::fmt;
+use spin::lazy::Lazy;
+
+static INFO: Lazy<CpuInfo> = Lazy::new(CpuInfo::detect);
+
+const MAX_CACHES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// One cache level as described by CPUID leaf 4 (Intel) or 0x8000_001D (AMD).
+#[derive(Debug, Clone, Copy)]
+pub struct CacheInfo {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub size: usize,
+    pub line_size: usize,
+    pub ways: usize,
+    pub sets: usize,
+    /// Maximum number of logical processors sharing the cache.
+    pub shared_by: usize,
+}
+
+impl fmt::Display for CacheInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            CacheKind::Data => "d",
+            CacheKind::Instruction => "i",
+            CacheKind::Unified => "",
+        };
+        write!(
+            f,
+            "L{}{}: {} KiB, {}-way, {} byte lines, shared by {}",
+            self.level,
+            kind,
+            self.size / 1024,
+            self.ways,
+            self.line_size,
+            self.shared_by
+        )
+    }
+}
+
+/// Decoded identification and topology information of the boot CPU.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    /// Initial APIC id from leaf 1, only 8 bits wide.
+    pub apic_id: u32,
+    /// Full x2APIC id from leaf 0xB, if the leaf exists.
+    pub x2apic_id: Option<u32>,
+    /// Number of addressable logical processors in the package.
+    pub logical_processors: u32,
+    caches: [Option<CacheInfo>; MAX_CACHES],
+    pub features: Features,
+}
+
+impl CpuInfo {
+    fn detect() -> Self {
+        let leaf0 = cpuid(0, 0);
+        let max_leaf = leaf0.eax;
+        let mut vendor = [0; 12];
+        vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+        vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+        vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+        let max_ext_leaf = cpuid(0x8000_0000, 0).eax;
+        let mut brand = [0; 48];
+        if max_ext_leaf >= 0x8000_0004 {
+            for (idx, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+                let regs = cpuid(leaf, 0);
+                for (reg_idx, reg) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+                    let at = idx * 16 + reg_idx * 4;
+                    brand[at..at + 4].copy_from_slice(&reg.to_le_bytes());
+                }
+            }
+        }
+
+        // The extended family and model only apply to some base families
+        let leaf1 = cpuid(1, 0);
+        let base_family = (leaf1.eax >> 8) & 0xF;
+        let base_model = (leaf1.eax >> 4) & 0xF;
+        let family = if base_family == 0xF {
+            base_family + ((leaf1.eax >> 20) & 0xFF)
+        } else {
+            base_family
+        };
+        let model = if base_family == 0x6 || base_family == 0xF {
+            base_model + (((leaf1.eax >> 16) & 0xF) << 4)
+        } else {
+            base_model
+        };
+
+        let x2apic_id = (max_leaf >= 0xB && cpuid(0xB, 0).ebx != 0).then(|| cpuid(0xB, 0).edx);
+
+        let mut info = CpuInfo {
+            vendor,
+            brand,
+            family,
+            model,
+            stepping: leaf1.eax & 0xF,
+            apic_id: leaf1.ebx >> 24,
+            x2apic_id,
+            logical_processors: (leaf1.ebx >> 16) & 0xFF,
+            caches: [None; MAX_CACHES],
+            features: *features::get(),
+        };
+
+        let cache_leaf = if info.vendor() == "GenuineIntel" && max_leaf >= 4 {
+            Some(4)
+        } else if max_ext_leaf >= 0x8000_001D && cpuid(0x8000_0001, 0).ecx & (1 << 22) != 0 {
+            // AMD only reports the deterministic cache parameters with topology extensions
+            Some(0x8000_001D)
+        } else {
+            None
+        };
+        if let Some(leaf) = cache_leaf {
+            for (subleaf, slot) in info.caches.iter_mut().enumerate() {
+                *slot = decode_cache(cpuid(leaf, subleaf as u32));
+                if slot.is_none() {
+                    break;
+                }
+            }
+        }
+        info
+    }
+
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    pub fn brand(&self) -> &str {
+        let len = self.brand.iter().position(|&b| b == 0).unwrap_or(48);
+        core::str::from_utf8(&self.brand[..len])
+            .unwrap_or("unknown")
+            .trim()
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item = &CacheInfo> {
+        self.caches.iter().flatten()
+    }
+}
+
+/// Decodes the cache parameter layout shared by leaf 4 and leaf 0x8000_001D.
+fn decode_cache(regs: core::arch::x86_64::CpuidResult) -> Option<CacheInfo> {
+    let kind = match regs.eax & 0x1F {
+        1 => CacheKind::Data,
+        2 => CacheKind::Instruction,
+        3 => CacheKind::Unified,
+        _ => return None,
+    };
+    let line_size = (regs.ebx & 0xFFF) as usize + 1;
+    let partitions = ((regs.ebx >> 12) & 0x3FF) as usize + 1;
+    let ways = (regs.ebx >> 22) as usize + 1;
+    let sets = regs.ecx as usize + 1;
+    Some(CacheInfo {
+        level: ((regs.eax >> 5) & 0x7) as u8,
+        kind,
+        size: ways * partitions * line_size * sets,
+        line_size,
+        ways,
+        sets,
+        shared_by: ((regs.eax >> 14) & 0xFFF) as usize + 1,
+    })
+}
+
+/// Information about the boot CPU.
+pub fn get() -> &'static CpuInfo {
+    &INFO
+}
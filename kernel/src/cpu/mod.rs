@@ -0,0 +1,61 @@
+pub mod features;
+pub mod fpu;
+pub mod info;
+
+use crate::serial_println;
+use core::arch::x86_64::{__cpuid_count, _rdtsc, CpuidResult};
+use features::Feature;
+use x86_64::{
+    instructions::random::RdRand,
+    registers::control::{Cr4, Cr4Flags},
+};
+
+/// Executes `cpuid` for `leaf`/`subleaf`.
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    __cpuid_count(leaf, subleaf)
+}
+
+/// Reads the time stamp counter.
+pub fn rdtsc() -> u64 {
+    // Every x86_64 CPU has a TSC
+    unsafe { _rdtsc() }
+}
+
+/// Best-effort random number from RDRAND, falling back to scrambled TSC bits. Good enough for
+/// address randomization, not for cryptography.
+pub fn random_u64() -> u64 {
+    if let Some(value) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return value;
+    }
+
+    // splitmix64 finalizer over the TSC
+    let mut z = rdtsc().wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Turns on the CR4 protection features the CPU supports and sets up the FPU.
+pub fn init() {
+    let features = features::get();
+    let wanted = [
+        (
+            Feature::Smep,
+            Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION,
+        ),
+        (Feature::Smap, Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        (Feature::Umip, Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION),
+        (Feature::FsGsBase, Cr4Flags::FSGSBASE),
+    ];
+
+    let mut enable = Cr4Flags::empty();
+    for (feature, flag) in wanted {
+        if features.has(feature) {
+            enable |= flag;
+        }
+    }
+    unsafe { Cr4::update(|flags| flags.insert(enable)) };
+    serial_println!("[CPU] CR4: {:?}", Cr4::read());
+
+    fpu::init();
+}
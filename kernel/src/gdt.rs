@@ -1,10 +1,8 @@
-use core::ptr::addr_of;
-
+use crate::memory::vmm;
 use spin::lazy::Lazy;
 use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtAddr;
 
 struct Selectors {
     code_selector: SegmentSelector,
@@ -17,11 +15,10 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
     let mut tss = TaskStateSegment::new();
     tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-        const STCK_SIZE: usize = 5 * 4096;
-        static mut STACK: [u8; STCK_SIZE] = [0; STCK_SIZE];
-
-        let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
-        stack_start + STCK_SIZE.try_into().unwrap()
+        const STCK_PAGES: u64 = 5;
+        // The stack is never freed, the TSS references it for the rest of the kernel's lifetime
+        let stack = vmm::allocate_stack(STCK_PAGES).expect("failed to allocate double fault stack");
+        stack.top()
     };
     tss
 });
@@ -0,0 +1,352 @@
+use crate::{framebuffer, gdt, hlt_loop, memory::vmm, serial};
+use core::{arch::global_asm, fmt};
+use x86_64::{
+    registers::control::{Cr2, Cr3},
+    structures::idt::{InterruptDescriptorTable, PageFaultErrorCode},
+    VirtAddr,
+};
+
+/// Register state saved by the exception entry stubs, lowest address first.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExceptionContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    /// Pushed by the CPU for some exceptions, 0 for the others.
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// Saves every general purpose register so the report can show them and resumable exceptions
+// can return to the interrupted code unchanged. Together with the 56 bytes pushed by the CPU
+// and the stub the 120 bytes of registers keep the stack 16 byte aligned for the call.
+global_asm!(
+    ".global exception_common",
+    "exception_common:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "cld",
+    "mov rdi, rsp",
+    "call {dispatch}",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    // Drop the vector and error code
+    "add rsp, 16",
+    "iretq",
+    dispatch = sym exception_dispatch,
+);
+
+/// Defines an entry stub that pushes a dummy error code where the CPU doesn't push one and the
+/// vector number, then continues in `exception_common`.
+macro_rules! exception_stub {
+    ($name:ident, $vector:literal) => {
+        exception_stub!(@emit $name, $vector, "push 0");
+    };
+    ($name:ident, $vector:literal, error_code) => {
+        exception_stub!(@emit $name, $vector, "");
+    };
+    (@emit $name:ident, $vector:literal, $push_error_code:literal) => {
+        global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            $push_error_code,
+            concat!("push ", stringify!($vector)),
+            "jmp exception_common",
+        );
+        extern "C" {
+            fn $name();
+        }
+    };
+}
+
+exception_stub!(divide_error_stub, 0);
+exception_stub!(debug_stub, 1);
+exception_stub!(nmi_stub, 2);
+exception_stub!(breakpoint_stub, 3);
+exception_stub!(overflow_stub, 4);
+exception_stub!(bound_range_stub, 5);
+exception_stub!(invalid_opcode_stub, 6);
+exception_stub!(device_not_available_stub, 7);
+exception_stub!(double_fault_stub, 8, error_code);
+exception_stub!(invalid_tss_stub, 10, error_code);
+exception_stub!(segment_not_present_stub, 11, error_code);
+exception_stub!(stack_segment_stub, 12, error_code);
+exception_stub!(general_protection_stub, 13, error_code);
+exception_stub!(page_fault_stub, 14, error_code);
+exception_stub!(x87_floating_point_stub, 16);
+exception_stub!(alignment_check_stub, 17, error_code);
+exception_stub!(machine_check_stub, 18);
+exception_stub!(simd_floating_point_stub, 19);
+exception_stub!(virtualization_stub, 20);
+exception_stub!(control_protection_stub, 21, error_code);
+exception_stub!(hv_injection_stub, 28);
+exception_stub!(vmm_communication_stub, 29, error_code);
+exception_stub!(security_stub, 30, error_code);
+
+fn stub_addr(stub: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(stub as usize as u64)
+}
+
+/// Points every architectural exception vector at its entry stub.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.divide_error
+            .set_handler_addr(stub_addr(divide_error_stub));
+        idt.debug.set_handler_addr(stub_addr(debug_stub));
+        idt.non_maskable_interrupt
+            .set_handler_addr(stub_addr(nmi_stub));
+        idt.breakpoint.set_handler_addr(stub_addr(breakpoint_stub));
+        idt.overflow.set_handler_addr(stub_addr(overflow_stub));
+        idt.bound_range_exceeded
+            .set_handler_addr(stub_addr(bound_range_stub));
+        idt.invalid_opcode
+            .set_handler_addr(stub_addr(invalid_opcode_stub));
+        idt.device_not_available
+            .set_handler_addr(stub_addr(device_not_available_stub));
+        idt.double_fault
+            .set_handler_addr(stub_addr(double_fault_stub))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss
+            .set_handler_addr(stub_addr(invalid_tss_stub));
+        idt.segment_not_present
+            .set_handler_addr(stub_addr(segment_not_present_stub));
+        idt.stack_segment_fault
+            .set_handler_addr(stub_addr(stack_segment_stub));
+        idt.general_protection_fault
+            .set_handler_addr(stub_addr(general_protection_stub));
+        idt.page_fault.set_handler_addr(stub_addr(page_fault_stub));
+        idt.x87_floating_point
+            .set_handler_addr(stub_addr(x87_floating_point_stub));
+        idt.alignment_check
+            .set_handler_addr(stub_addr(alignment_check_stub));
+        idt.machine_check
+            .set_handler_addr(stub_addr(machine_check_stub));
+        idt.simd_floating_point
+            .set_handler_addr(stub_addr(simd_floating_point_stub));
+        idt.virtualization
+            .set_handler_addr(stub_addr(virtualization_stub));
+        idt.cp_protection_exception
+            .set_handler_addr(stub_addr(control_protection_stub));
+        idt.hv_injection_exception
+            .set_handler_addr(stub_addr(hv_injection_stub));
+        idt.vmm_communication_exception
+            .set_handler_addr(stub_addr(vmm_communication_stub));
+        idt.security_exception
+            .set_handler_addr(stub_addr(security_stub));
+    }
+}
+
+/// Name and mnemonic of an exception vector.
+pub(super) fn exception_name(vector: u64) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("Divide Error", "#DE"),
+        1 => ("Debug", "#DB"),
+        2 => ("Non-Maskable Interrupt", "NMI"),
+        3 => ("Breakpoint", "#BP"),
+        4 => ("Overflow", "#OF"),
+        5 => ("Bound Range Exceeded", "#BR"),
+        6 => ("Invalid Opcode", "#UD"),
+        7 => ("Device Not Available", "#NM"),
+        8 => ("Double Fault", "#DF"),
+        10 => ("Invalid TSS", "#TS"),
+        11 => ("Segment Not Present", "#NP"),
+        12 => ("Stack-Segment Fault", "#SS"),
+        13 => ("General Protection Fault", "#GP"),
+        14 => ("Page Fault", "#PF"),
+        16 => ("x87 Floating-Point Exception", "#MF"),
+        17 => ("Alignment Check", "#AC"),
+        18 => ("Machine Check", "#MC"),
+        19 => ("SIMD Floating-Point Exception", "#XM"),
+        20 => ("Virtualization Exception", "#VE"),
+        21 => ("Control Protection Exception", "#CP"),
+        28 => ("Hypervisor Injection Exception", "#HV"),
+        29 => ("VMM Communication Exception", "#VC"),
+        30 => ("Security Exception", "#SX"),
+        _ => ("Unknown Exception", "#??"),
+    }
+}
+
+/// Called by `exception_common` for every exception. Returning resumes the interrupted code.
+extern "C" fn exception_dispatch(context: &mut ExceptionContext) {
+    super::stats::count(context.vector as u8);
+    match context.vector {
+        // Debug traps, breakpoints and NMIs don't corrupt anything, report them and go on
+        1..=3 => report(context),
+        14 => {
+            let error_code = PageFaultErrorCode::from_bits_truncate(context.error_code);
+            let resolved = Cr2::read().is_ok_and(|addr| vmm::handle_page_fault(addr, error_code));
+            if !resolved {
+                crash(context);
+            }
+        }
+        _ => crash(context),
+    }
+}
+
+fn crash(context: &ExceptionContext) -> ! {
+    report(context);
+    hlt_loop();
+}
+
+/// Prints the report on serial and, once it is up, the framebuffer.
+fn report(context: &ExceptionContext) {
+    print_report(serial::_print, context);
+    if framebuffer::FBWRITER.get().is_some() {
+        print_report(framebuffer::_print, context);
+    }
+}
+
+fn print_report(print: fn(fmt::Arguments), context: &ExceptionContext) {
+    let (name, mnemonic) = exception_name(context.vector);
+    print(format_args!(
+        "EXCEPTION: {} ({}, vector {})\n",
+        name, mnemonic, context.vector
+    ));
+    print(format_args!(
+        "Error code: {:#x} ({})\n",
+        context.error_code,
+        ErrorCode(context)
+    ));
+    print(format_args!(
+        "RIP: {:#018x} CS: {:#06x} RFLAGS: {:#010x}\n",
+        context.rip, context.cs, context.rflags
+    ));
+    print(format_args!(
+        "RSP: {:#018x} SS: {:#06x}\n",
+        context.rsp, context.ss
+    ));
+
+    let cr2 = Cr2::read();
+    let region = cr2
+        .as_ref()
+        .map_or("non-canonical address", |addr| vmm::describe_addr(*addr));
+    print(format_args!("CR2: {:?} ({})\n", cr2, region));
+    print(format_args!("CR3: {:?}\n", Cr3::read().0.start_address()));
+
+    let registers = [
+        ("RAX", context.rax),
+        ("RBX", context.rbx),
+        ("RCX", context.rcx),
+        ("RDX", context.rdx),
+        ("RSI", context.rsi),
+        ("RDI", context.rdi),
+        ("RBP", context.rbp),
+        ("R8 ", context.r8),
+        ("R9 ", context.r9),
+        ("R10", context.r10),
+        ("R11", context.r11),
+        ("R12", context.r12),
+        ("R13", context.r13),
+        ("R14", context.r14),
+        ("R15", context.r15),
+    ];
+    for row in registers.chunks(3) {
+        for (name, value) in row {
+            print(format_args!("{}: {:#018x}  ", name, value));
+        }
+        print(format_args!("\n"));
+    }
+}
+
+/// Human readable decoding of the error code, depending on the exception.
+struct ErrorCode<'a>(&'a ExceptionContext);
+
+impl fmt::Display for ErrorCode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.0.error_code;
+        match self.0.vector {
+            14 => {
+                let flags = PageFaultErrorCode::from_bits_truncate(code);
+                let cause = if flags.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+                    "protection violation"
+                } else {
+                    "page not present"
+                };
+                let access = if flags.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+                    "instruction fetch"
+                } else if flags.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+                    "write"
+                } else {
+                    "read"
+                };
+                let mode = if flags.contains(PageFaultErrorCode::USER_MODE) {
+                    "user"
+                } else {
+                    "kernel"
+                };
+                write!(f, "{} on {} in {} mode", cause, access, mode)
+            }
+            // Selector error codes
+            10..=13 if code != 0 => {
+                let table = match (code >> 1) & 0b11 {
+                    0 => "GDT",
+                    1 | 3 => "IDT",
+                    _ => "LDT",
+                };
+                write!(f, "{} index {}", table, (code >> 3) & 0x1FFF)?;
+                if code & 1 != 0 {
+                    f.write_str(", external event")?;
+                }
+                Ok(())
+            }
+            21 => {
+                let reason = match code & 0x7FFF {
+                    1 => "near return",
+                    2 => "far return",
+                    3 => "missing ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown",
+                };
+                f.write_str(reason)
+            }
+            8 | 10..=13 | 17 | 29 | 30 if code == 0 => f.write_str("none"),
+            8 | 10..=13 | 17 | 29 | 30 => f.write_str("not decoded"),
+            _ => f.write_str("not pushed"),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use super::APIC_REGION_SIZE;
+use crate::{
+    cpu,
+    memory::mmio::{map_mmio, CacheMode},
+    serial_println,
+};
+use acpi::platform::interrupt::{
+    InterruptSourceOverride, IoApic as MadtIoApic, Polarity, TriggerMode,
+};
+use alloc::vec::Vec;
+use spin::{mutex::Mutex, once::Once};
+use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
+use x86_64::PhysAddr;
+
+/// Number of legacy ISA IRQs, identity mapped to GSIs unless the MADT overrides them.
+const ISA_IRQS: u8 = 16;
+
+static IO_APICS: Once<Vec<IoApicDevice>> = Once::new();
+static ISA_OVERRIDES: Once<Vec<IsaOverride>> = Once::new();
+static ROUTES: Mutex<Vec<Route>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingError {
+    /// IOAPICs haven't been set up yet.
+    NotInitialized,
+    /// No IOAPIC handles the GSI.
+    NoSuchGsi(u32),
+    /// The GSI already delivers another vector.
+    AlreadyRouted(u32),
+}
+
+/// One IOAPIC from the MADT and the range of GSIs it serves.
+struct IoApicDevice {
+    id: u8,
+    gsi_base: u32,
+    gsi_count: u32,
+    regs: Mutex<IoApic>,
+}
+
+/// Remapping of an ISA IRQ announced by the MADT.
+#[derive(Debug, Clone, Copy)]
+struct IsaOverride {
+    irq: u8,
+    gsi: u32,
+    flags: IrqFlags,
+}
+
+/// A GSI that was pointed at a vector.
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub gsi: u32,
+    pub vector: u8,
+    pub flags: IrqFlags,
+}
+
+/// Maps every IOAPIC the MADT lists, masks all of their inputs and records the ISA overrides.
+pub fn init(io_apics: &[MadtIoApic], overrides: &[InterruptSourceOverride]) {
+    IO_APICS.call_once(|| {
+        io_apics
+            .iter()
+            .map(|madt| {
+                let virt = map_mmio(
+                    PhysAddr::new(madt.address as u64),
+                    APIC_REGION_SIZE,
+                    CacheMode::Uncached,
+                )
+                .expect("failed to map IOAPIC")
+                .leak();
+                let mut regs = unsafe { IoApic::new(virt.as_u64()) };
+                // Starts out with every entry masked
+                unsafe { regs.init(super::INTERRUPT_BASE) };
+                let gsi_count = unsafe { regs.max_table_entry() } as u32 + 1;
+                serial_println!(
+                    "[IOAPIC] id {} at {:#x}, GSIs {}-{}",
+                    madt.id,
+                    madt.address,
+                    madt.global_system_interrupt_base,
+                    madt.global_system_interrupt_base + gsi_count - 1
+                );
+                IoApicDevice {
+                    id: madt.id,
+                    gsi_base: madt.global_system_interrupt_base,
+                    gsi_count,
+                    regs: Mutex::new(regs),
+                }
+            })
+            .collect()
+    });
+
+    ISA_OVERRIDES.call_once(|| {
+        overrides
+            .iter()
+            .map(|over| IsaOverride {
+                irq: over.isa_source,
+                gsi: over.global_system_interrupt,
+                flags: override_flags(over.polarity, over.trigger_mode),
+            })
+            .collect()
+    });
+}
+
+/// Converts MADT flags, ISA interrupts default to active high and edge triggered.
+fn override_flags(polarity: Polarity, trigger_mode: TriggerMode) -> IrqFlags {
+    let mut flags = IrqFlags::empty();
+    if matches!(polarity, Polarity::ActiveLow) {
+        flags |= IrqFlags::LOW_ACTIVE;
+    }
+    if matches!(trigger_mode, TriggerMode::Level) {
+        flags |= IrqFlags::LEVEL_TRIGGERED;
+    }
+    flags
+}
+
+/// GSI and flags an ISA IRQ is delivered with after applying the MADT overrides.
+pub fn isa_irq_to_gsi(irq: u8) -> (u32, IrqFlags) {
+    assert!(irq < ISA_IRQS, "{} is not an ISA IRQ", irq);
+    ISA_OVERRIDES
+        .get()
+        .and_then(|overrides| overrides.iter().find(|over| over.irq == irq))
+        .map_or((irq as u32, IrqFlags::empty()), |over| {
+            (over.gsi, over.flags)
+        })
+}
+
+/// Flags `gsi` is delivered with unless told otherwise: those of an ISA override targeting it,
+/// the ISA defaults for the first 16 GSIs and PCI's active low, level triggered above.
+pub fn default_flags(gsi: u32) -> IrqFlags {
+    let overridden = ISA_OVERRIDES
+        .get()
+        .and_then(|overrides| overrides.iter().find(|over| over.gsi == gsi));
+    match overridden {
+        Some(over) => over.flags,
+        None if gsi < ISA_IRQS as u32 => IrqFlags::empty(),
+        None => IrqFlags::LOW_ACTIVE | IrqFlags::LEVEL_TRIGGERED,
+    }
+}
+
+/// Whether some IOAPIC handles `gsi`.
+pub fn has_gsi(gsi: u32) -> bool {
+    find_io_apic(gsi).is_ok()
+}
+
+/// Whether `gsi` is currently routed to a vector.
+pub fn is_routed(gsi: u32) -> bool {
+    ROUTES.lock().iter().any(|route| route.gsi == gsi)
+}
+
+/// Snapshot of all active routes.
+pub fn routes() -> Vec<Route> {
+    ROUTES.lock().clone()
+}
+
+fn find_io_apic(gsi: u32) -> Result<&'static IoApicDevice, RoutingError> {
+    IO_APICS
+        .get()
+        .ok_or(RoutingError::NotInitialized)?
+        .iter()
+        .find(|io_apic| (io_apic.gsi_base..io_apic.gsi_base + io_apic.gsi_count).contains(&gsi))
+        .ok_or(RoutingError::NoSuchGsi(gsi))
+}
+
+/// Delivers `gsi` as `vector` to the boot CPU.
+pub fn route_gsi(gsi: u32, vector: u8, flags: IrqFlags) -> Result<(), RoutingError> {
+    let io_apic = find_io_apic(gsi)?;
+    let mut routes = ROUTES.lock();
+    match routes.iter().find(|route| route.gsi == gsi) {
+        Some(route) if route.vector != vector => return Err(RoutingError::AlreadyRouted(gsi)),
+        Some(_) => {}
+        None => routes.push(Route { gsi, vector, flags }),
+    }
+
+    let mut entry = RedirectionTableEntry::default();
+    entry.set_mode(IrqMode::Fixed);
+    entry.set_flags(flags);
+    entry.set_dest(cpu::info::get().apic_id as u8);
+    entry.set_vector(vector);
+
+    let pin = (gsi - io_apic.gsi_base) as u8;
+    let mut regs = io_apic.regs.lock();
+    unsafe {
+        regs.set_table_entry(pin, entry);
+        regs.enable_irq(pin);
+    }
+    Ok(())
+}
+
+/// Delivers ISA IRQ `irq` as `vector`, honouring the MADT overrides. Returns the GSI used.
+pub fn route_isa_irq(irq: u8, vector: u8) -> Result<u32, RoutingError> {
+    let (gsi, flags) = isa_irq_to_gsi(irq);
+    route_gsi(gsi, vector, flags)?;
+    Ok(gsi)
+}
+
+/// Masks `gsi` and forgets its route.
+pub fn unroute_gsi(gsi: u32) -> Result<(), RoutingError> {
+    let io_apic = find_io_apic(gsi)?;
+    ROUTES.lock().retain(|route| route.gsi != gsi);
+    let pin = (gsi - io_apic.gsi_base) as u8;
+    unsafe { io_apic.regs.lock().disable_irq(pin) };
+    Ok(())
+}
+
+/// IOAPIC id and input a GSI arrives on, for diagnostics.
+pub fn gsi_location(gsi: u32) -> Option<(u8, u8)> {
+    let io_apic = find_io_apic(gsi).ok()?;
+    Some((io_apic.id, (gsi - io_apic.gsi_base) as u8))
+}
@@ -0,0 +1,216 @@
+use super::{ioapic, ioapic::RoutingError, stats, INTERRUPT_BASE, LAPIC};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    arch::global_asm,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::{mutex::Mutex, rwlock::RwLock};
+use x86_64::{
+    instructions::interrupts::without_interrupts, structures::idt::InterruptDescriptorTable,
+    VirtAddr,
+};
+
+/// First vector handed out by [`register_irq`], everything below is taken by the static handlers.
+pub const FIRST_DYNAMIC_VECTOR: u8 = 0x80;
+/// Number of vectors handed out by [`register_irq`], the ones above are left to the LAPIC.
+pub const DYNAMIC_VECTORS: usize = 0x70;
+/// Every vector from here on gets an entry stub, static handlers replace theirs afterwards.
+const FIRST_STUB_VECTOR: u8 = INTERRUPT_BASE;
+const STUBS: usize = 256 - FIRST_STUB_VECTOR as usize;
+/// Distance between two entry stubs.
+const STUB_SIZE: usize = 16;
+
+/// Handlers of every dynamic vector. Only ever written with interrupts disabled, so the
+/// dispatcher never spins on a lock held by the code it interrupted.
+static HANDLERS: [RwLock<Vec<Handler>>; DYNAMIC_VECTORS] =
+    [const { RwLock::new(Vec::new()) }; DYNAMIC_VECTORS];
+/// GSIs that were routed to a dynamic vector.
+static LINES: Mutex<Vec<Line>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// Every dynamic vector is in use.
+    NoFreeVector,
+    /// The GSI couldn't be routed, e.g. because a static handler already owns it.
+    Route(RoutingError),
+}
+
+struct Handler {
+    id: u64,
+    handler: Box<dyn Fn() + Send + Sync>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    gsi: u32,
+    vector: u8,
+}
+
+/// Keeps a handler registered with [`register_irq`]. Dropping it unregisters the handler and
+/// releases the vector and the GSI once no other handler shares them.
+#[derive(Debug)]
+#[must_use = "the handler is unregistered when the handle is dropped"]
+pub struct IrqHandle {
+    id: u64,
+    gsi: u32,
+    vector: u8,
+}
+
+impl IrqHandle {
+    pub fn gsi(&self) -> u32 {
+        self.gsi
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.vector
+    }
+}
+
+impl Drop for IrqHandle {
+    fn drop(&mut self) {
+        // The handler itself is dropped with interrupts enabled again
+        let _removed = without_interrupts(|| {
+            let mut handlers = HANDLERS[slot(self.vector)].write();
+            let idx = handlers.iter().position(|handler| handler.id == self.id)?;
+            let removed = handlers.remove(idx);
+            if handlers.is_empty() {
+                LINES.lock().retain(|line| line.gsi != self.gsi);
+                // Only fails if the IOAPIC vanished, which can't happen
+                let _ = ioapic::unroute_gsi(self.gsi);
+            }
+            Some(removed)
+        });
+    }
+}
+
+// Saves the registers the System V ABI lets the dispatcher clobber. The 48 bytes pushed by the
+// CPU and the stub plus 72 bytes of registers and 8 bytes of padding keep the stack 16 byte
+// aligned for the call.
+global_asm!(
+    "irq_common:",
+    "push rax",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "sub rsp, 8",
+    "cld",
+    "mov rdi, [rsp + 80]",
+    "call {dispatch}",
+    "add rsp, 8",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rax",
+    // Drop the vector
+    "add rsp, 8",
+    "iretq",
+    dispatch = sym irq_dispatch,
+);
+
+// One stub per vector, `STUB_SIZE` bytes apart, each pushing its vector number.
+global_asm!(
+    ".global irq_stubs",
+    ".balign {stub_size}",
+    "irq_stubs:",
+    ".set vector, {first}",
+    ".rept {count}",
+    ".balign {stub_size}",
+    "push vector",
+    "jmp irq_common",
+    ".set vector, vector + 1",
+    ".endr",
+    first = const FIRST_STUB_VECTOR,
+    count = const STUBS,
+    stub_size = const STUB_SIZE,
+);
+
+extern "C" {
+    fn irq_stubs();
+}
+
+fn is_dynamic(vector: u8) -> bool {
+    (FIRST_DYNAMIC_VECTOR..FIRST_DYNAMIC_VECTOR + DYNAMIC_VECTORS as u8).contains(&vector)
+}
+
+fn slot(vector: u8) -> usize {
+    (vector - FIRST_DYNAMIC_VECTOR) as usize
+}
+
+/// Runs every handler sharing `vector` and acknowledges the interrupt. Vectors without any
+/// handler end up here too, so they still show up in the statistics.
+extern "C" fn irq_dispatch(vector: u64) {
+    let vector = vector as u8;
+    stats::count(vector);
+    if is_dynamic(vector) {
+        if let Some(handlers) = HANDLERS[slot(vector)].try_read() {
+            for handler in handlers.iter() {
+                (handler.handler)();
+            }
+        }
+    }
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+/// Number of handlers registered for `vector`, 0 for vectors outside the dynamic range.
+pub fn handler_count(vector: u8) -> usize {
+    if !is_dynamic(vector) {
+        return 0;
+    }
+    without_interrupts(|| HANDLERS[slot(vector)].read().len())
+}
+
+/// Points every vector above the exceptions at its entry stub.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    let stubs = irq_stubs as *const () as u64;
+    for i in 0..STUBS {
+        let addr = VirtAddr::new(stubs + (i * STUB_SIZE) as u64);
+        unsafe { idt[FIRST_STUB_VECTOR + i as u8].set_handler_addr(addr) };
+    }
+}
+
+/// Runs `handler` whenever `gsi` fires. Handlers registered for the same GSI share its vector
+/// and are all called on every interrupt, so each has to check whether its device raised it.
+///
+/// Handlers run in interrupt context and must neither block nor allocate. The LAPIC EOI is
+/// sent after the last one returned.
+pub fn register_irq<F>(gsi: u32, handler: F) -> Result<IrqHandle, IrqError>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let handler = Handler {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        handler: Box::new(handler),
+    };
+    let id = handler.id;
+
+    without_interrupts(|| {
+        let mut lines = LINES.lock();
+        let vector = match lines.iter().find(|line| line.gsi == gsi) {
+            Some(line) => line.vector,
+            None => {
+                let vector = (0..DYNAMIC_VECTORS as u8)
+                    .map(|i| FIRST_DYNAMIC_VECTOR + i)
+                    .find(|&vector| lines.iter().all(|line| line.vector != vector))
+                    .ok_or(IrqError::NoFreeVector)?;
+                ioapic::route_gsi(gsi, vector, ioapic::default_flags(gsi))
+                    .map_err(IrqError::Route)?;
+                lines.push(Line { gsi, vector });
+                vector
+            }
+        };
+        HANDLERS[slot(vector)].write().push(handler);
+        Ok(IrqHandle { id, gsi, vector })
+    })
+}
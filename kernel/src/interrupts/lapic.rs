@@ -0,0 +1,98 @@
+use super::{lapic_read, lapic_write, stats, InterruptIndex, LAPIC};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+use x86_64::structures::idt::InterruptStackFrame;
+
+const LAPIC_ERROR_STATUS: usize = 0x280;
+
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+/// Every ESR bit seen since boot.
+static ERROR_BITS: AtomicU32 = AtomicU32::new(0);
+static LAST_ERROR: AtomicU32 = AtomicU32::new(0);
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Contents of the LAPIC Error Status Register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorStatus(pub u32);
+
+impl ErrorStatus {
+    const BITS: [(u32, &'static str); 8] = [
+        (1 << 0, "send checksum error"),
+        (1 << 1, "receive checksum error"),
+        (1 << 2, "send accept error"),
+        (1 << 3, "receive accept error"),
+        (1 << 4, "redirectable IPI"),
+        (1 << 5, "send illegal vector"),
+        (1 << 6, "received illegal vector"),
+        (1 << 7, "illegal register address"),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for ErrorStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let mut names = Self::BITS
+            .iter()
+            .filter(|(bit, _)| self.0 & bit != 0)
+            .map(|(_, name)| name);
+        if let Some(first) = names.next() {
+            write!(f, "{}", first)?;
+        }
+        for name in names {
+            write!(f, ", {}", name)?;
+        }
+        let unknown = Self::BITS.iter().fold(self.0, |rest, (bit, _)| rest & !bit);
+        if unknown != 0 {
+            write!(f, " (unknown bits {:#x})", unknown)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of LAPIC error interrupts received so far.
+pub fn errors() -> u64 {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+/// Error status of the most recent LAPIC error interrupt.
+pub fn last_error() -> ErrorStatus {
+    ErrorStatus(LAST_ERROR.load(Ordering::Relaxed))
+}
+
+/// Every error reported since boot.
+pub fn all_errors() -> ErrorStatus {
+    ErrorStatus(ERROR_BITS.load(Ordering::Relaxed))
+}
+
+/// Number of spurious interrupts received so far.
+pub fn spurious() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+pub(super) extern "x86-interrupt" fn error_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::LapicError.as_u8());
+    // Writing the ESR latches the errors collected since the last write so they can be read
+    let status = unsafe {
+        lapic_write(LAPIC_ERROR_STATUS, 0);
+        lapic_read(LAPIC_ERROR_STATUS)
+    };
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    LAST_ERROR.store(status, Ordering::Relaxed);
+    ERROR_BITS.fetch_or(status, Ordering::Relaxed);
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+pub(super) extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::Spurious.as_u8());
+    // The LAPIC doesn't mark spurious interrupts in service, so they must not be acknowledged
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
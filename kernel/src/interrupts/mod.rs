@@ -0,0 +1,290 @@
+pub mod exceptions;
+pub mod ioapic;
+pub mod irq;
+pub mod lapic;
+pub mod stats;
+
+pub use irq::{register_irq, IrqError, IrqHandle};
+
+use crate::{
+    memory::mmio::{map_mmio, CacheMode},
+    println, task, time,
+};
+use acpi::platform::interrupt::{InterruptSourceOverride, IoApic as MadtIoApic};
+use spin::{lazy::Lazy, mutex::Mutex, once::Once};
+use x2apic::lapic::{LocalApic, LocalApicBuilder};
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
+    PhysAddr, VirtAddr,
+};
+
+pub const LAPIC_PHYS_ADDR: u64 = 0xFEE00000;
+const APIC_REGION_SIZE: usize = 0x1000;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const X2APIC_MSR_BASE: u32 = 0x800;
+const LAPIC_ID: usize = 0x020;
+const LAPIC_TIMER_CURRENT_COUNT: usize = 0x390;
+
+/// Where the xAPIC registers are mapped, set when [`LAPIC`] is first used.
+static LAPIC_BASE: Once<VirtAddr> = Once::new();
+
+pub const INTERRUPT_BASE: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = INTERRUPT_BASE,
+    Keyboard,
+    Cascade,
+    COM2,
+    COM1,
+    LPT2,
+    Floppy,
+    LPT1,
+    CMOSClock = INTERRUPT_BASE + 0x50,
+    Peripheral1,
+    Peripheral2,
+    Peripheral3,
+    Mouse,
+    Coprocessor,
+    PrimaryATA,
+    SecondaryATA,
+    Hpet,
+    HpetTimer,
+    LapicError = 0xFE,
+    Spurious = 0xFF,
+}
+
+impl InterruptIndex {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn as_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Vectors with a fixed handler and what raises them.
+const STATIC_SOURCES: [(InterruptIndex, &str); 10] = [
+    (InterruptIndex::Timer, "LAPIC timer"),
+    (InterruptIndex::Keyboard, "PS/2 keyboard"),
+    (InterruptIndex::CMOSClock, "RTC"),
+    (InterruptIndex::Mouse, "PS/2 mouse"),
+    (InterruptIndex::PrimaryATA, "Primary ATA"),
+    (InterruptIndex::SecondaryATA, "Secondary ATA"),
+    (InterruptIndex::Hpet, "HPET tick"),
+    (InterruptIndex::HpetTimer, "HPET timers"),
+    (InterruptIndex::LapicError, "LAPIC error"),
+    (InterruptIndex::Spurious, "Spurious"),
+];
+
+pub static LAPIC: Lazy<Mutex<LocalApic>> = Lazy::new(|| {
+    let lapic_virt_addr = map_mmio(
+        PhysAddr::new(LAPIC_PHYS_ADDR),
+        APIC_REGION_SIZE,
+        CacheMode::Uncached,
+    )
+    .expect("failed to map LAPIC")
+    .leak();
+    LAPIC_BASE.call_once(|| lapic_virt_addr);
+    let lapic = LocalApicBuilder::new()
+        .timer_vector(InterruptIndex::Timer.as_usize())
+        .error_vector(InterruptIndex::LapicError.as_usize())
+        .spurious_vector(InterruptIndex::Spurious.as_usize())
+        .set_xapic_base(lapic_virt_addr.as_u64())
+        .build()
+        .unwrap_or_else(|err| panic!("{}", err));
+    Mutex::new(lapic)
+});
+
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+    exceptions::install(&mut idt);
+    irq::install(&mut idt);
+    idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
+    idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+    idt[InterruptIndex::Mouse.as_u8()].set_handler_fn(mouse_interrupt_handler);
+    idt[InterruptIndex::PrimaryATA.as_u8()].set_handler_fn(primary_ata_interrupt_handler);
+    idt[InterruptIndex::SecondaryATA.as_u8()].set_handler_fn(secondary_ata_interrupt_handler);
+    idt[InterruptIndex::Hpet.as_u8()].set_handler_fn(hpet_interrupt_handler);
+    idt[InterruptIndex::HpetTimer.as_u8()].set_handler_fn(hpet_timer_interrupt_handler);
+    idt[InterruptIndex::CMOSClock.as_u8()].set_handler_fn(rtc_interrupt_handler);
+    idt[InterruptIndex::LapicError.as_u8()].set_handler_fn(lapic::error_interrupt_handler);
+    idt[InterruptIndex::Spurious.as_u8()].set_handler_fn(lapic::spurious_interrupt_handler);
+    idt
+});
+
+pub fn init_idt() {
+    IDT.load();
+}
+
+/// Reads a LAPIC register the x2apic crate doesn't expose, through MSRs in x2APIC mode and
+/// through the MMIO mapping otherwise.
+///
+/// # Safety
+///
+/// [`LAPIC`] must have been initialized and `offset` must be a readable register.
+unsafe fn lapic_read(offset: usize) -> u32 {
+    if x2apic_enabled() {
+        Msr::new(X2APIC_MSR_BASE + (offset >> 4) as u32).read() as u32
+    } else {
+        let base = *LAPIC_BASE.get().expect("LAPIC not initialized");
+        (base + offset as u64).as_ptr::<u32>().read_volatile()
+    }
+}
+
+/// Writes a LAPIC register the x2apic crate doesn't expose, see [`lapic_read`].
+///
+/// # Safety
+///
+/// [`LAPIC`] must have been initialized and `offset` must be a writable register.
+unsafe fn lapic_write(offset: usize, value: u32) {
+    if x2apic_enabled() {
+        Msr::new(X2APIC_MSR_BASE + (offset >> 4) as u32).write(value as u64);
+    } else {
+        let base = *LAPIC_BASE.get().expect("LAPIC not initialized");
+        (base + offset as u64)
+            .as_mut_ptr::<u32>()
+            .write_volatile(value);
+    }
+}
+
+fn x2apic_enabled() -> bool {
+    unsafe { Msr::new(IA32_APIC_BASE_MSR).read() & APIC_BASE_X2APIC_ENABLE != 0 }
+}
+
+/// LAPIC id of the executing CPU.
+fn lapic_id() -> u32 {
+    Lazy::force(&LAPIC);
+    let id = unsafe { lapic_read(LAPIC_ID) };
+    // xAPICs keep their 8 bit id in the top byte
+    if x2apic_enabled() {
+        id
+    } else {
+        id >> 24
+    }
+}
+
+/// Current count of the LAPIC timer.
+pub fn lapic_timer_current() -> u32 {
+    Lazy::force(&LAPIC);
+    unsafe { lapic_read(LAPIC_TIMER_CURRENT_COUNT) }
+}
+
+pub unsafe fn init_apic() {
+    LAPIC.lock().enable();
+    stats::register_cpu(lapic_id());
+    // The timer stays masked until time::init calibrated it
+    LAPIC.lock().disable_timer();
+}
+
+/// Sets up the IOAPICs described by the MADT and routes the legacy devices through them.
+pub fn init_ioapics(io_apics: &[MadtIoApic], overrides: &[InterruptSourceOverride]) {
+    ioapic::init(io_apics, overrides);
+
+    let legacy_devices = [
+        (1, InterruptIndex::Keyboard),
+        (12, InterruptIndex::Mouse),
+        (14, InterruptIndex::PrimaryATA),
+        (15, InterruptIndex::SecondaryATA),
+    ];
+    for (irq, index) in legacy_devices {
+        if let Err(err) = ioapic::route_isa_irq(irq, index.as_u8()) {
+            println!("[IOAPIC] failed to route IRQ {}: {:?}", irq, err);
+        }
+    }
+}
+
+/// What raises `vector`, for diagnostics.
+pub fn vector_source(vector: u8) -> Option<&'static str> {
+    if vector < INTERRUPT_BASE {
+        return Some(exceptions::exception_name(vector as u64).0);
+    }
+    if let Some((_, source)) = STATIC_SOURCES
+        .iter()
+        .find(|(index, _)| index.as_u8() == vector)
+    {
+        return Some(source);
+    }
+    if irq::handler_count(vector) > 0 {
+        return Some("Registered handlers");
+    }
+    None
+}
+
+/// Advances the kernel clock and the timers waiting on it.
+fn kernel_tick() {
+    time::tick();
+    task::time::on_tick();
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::Timer.as_u8());
+    kernel_tick();
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn hpet_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::Hpet.as_u8());
+    time::hpet::on_interrupt();
+    if time::hpet::drives_tick() {
+        kernel_tick();
+    }
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn hpet_timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::HpetTimer.as_u8());
+    time::hpet::on_interrupt();
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn rtc_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::CMOSClock.as_u8());
+    time::rtc::on_interrupt();
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::Keyboard.as_u8());
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    crate::task::keyboard::add_scancode(scancode);
+
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::Mouse.as_u8());
+    //TODO implement mouse input
+    // Enable mouse:
+    // write(0xd4) -> 0x64
+    // write(0xf4) -> 0x60
+    // wait while !(read(0x64) & 1)
+    // ack <- read(0x60)
+    //
+    // begin reading mouse inputs
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::PrimaryATA.as_u8());
+    println!("PRIMARY ATA INT");
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    stats::count(InterruptIndex::SecondaryATA.as_u8());
+    println!("SECONDARY ATA INT");
+    unsafe { LAPIC.lock().end_of_interrupt() }
+}
@@ -0,0 +1,60 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// CPUs the counters have room for.
+pub const MAX_CPUS: usize = 8;
+const VECTORS: usize = 256;
+const NO_CPU: u32 = u32::MAX;
+
+/// Interrupts received per CPU and vector.
+static COUNTS: [[AtomicU64; VECTORS]; MAX_CPUS] =
+    [const { [const { AtomicU64::new(0) }; VECTORS] }; MAX_CPUS];
+/// LAPIC id of every registered CPU, indexed like [`COUNTS`].
+static CPU_APIC_IDS: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(NO_CPU) }; MAX_CPUS];
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Gives the CPU with LAPIC id `apic_id` its own set of counters.
+pub(super) fn register_cpu(apic_id: u32) {
+    if CPU_APIC_IDS
+        .iter()
+        .any(|id| id.load(Ordering::Relaxed) == apic_id)
+    {
+        return;
+    }
+    let cpu = ONLINE.fetch_add(1, Ordering::Relaxed);
+    assert!(cpu < MAX_CPUS, "more than {} CPUs", MAX_CPUS);
+    CPU_APIC_IDS[cpu].store(apic_id, Ordering::Relaxed);
+}
+
+/// Counter index of the executing CPU.
+fn current_cpu() -> usize {
+    // With a single CPU there is no need to ask the LAPIC, which also isn't mapped yet when the
+    // first page faults arrive
+    if ONLINE.load(Ordering::Relaxed) <= 1 {
+        return 0;
+    }
+    let apic_id = super::lapic_id();
+    CPU_APIC_IDS
+        .iter()
+        .position(|id| id.load(Ordering::Relaxed) == apic_id)
+        .unwrap_or(0)
+}
+
+/// Called on entry of every interrupt and exception handler.
+pub(super) fn count(vector: u8) {
+    COUNTS[current_cpu()][vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of CPUs with their own counters.
+pub fn cpus() -> usize {
+    ONLINE.load(Ordering::Relaxed).max(1)
+}
+
+/// Times `vector` was raised on `cpu`.
+pub fn count_on(vector: u8, cpu: usize) -> u64 {
+    COUNTS[cpu][vector as usize].load(Ordering::Relaxed)
+}
+
+/// Times `vector` was raised on any CPU.
+pub fn total(vector: u8) -> u64 {
+    (0..cpus()).map(|cpu| count_on(vector, cpu)).sum()
+}
@@ -4,6 +4,7 @@
 extern crate alloc;
 
 pub mod allocator;
+pub mod cpu;
 pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
@@ -11,6 +12,7 @@ pub mod memory;
 pub mod ata;
 pub mod serial;
 pub mod task;
+pub mod time;
 
 pub fn hlt_loop() -> ! {
     loop {
@@ -19,6 +21,9 @@ pub fn hlt_loop() -> ! {
 }
 
 pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
+    // Enable the protection features the CPU supports
+    cpu::init();
+
     // Set up initial framebuffer logic
     framebuffer::init(boot_info);
 
@@ -26,7 +31,7 @@ pub fn init(boot_info: &'static mut bootloader_api::BootInfo) {
     gdt::init();
     interrupts::init_idt();
     unsafe {
-        interrupts::init_apic(0);
+        interrupts::init_apic();
     };
     x86_64::instructions::interrupts::enable();
 }
@@ -17,13 +17,16 @@ use kernel::{
     allocator,
     ata::pio::{test_read, test_write},
     framebuffer::FBWRITER,
-    memory::{self, BootInfoFrameAllocator},
+    interrupts,
+    memory::{
+        self,
+        mmio::{map_mmio, CacheMode, MmioRegion},
+    },
     println, serial_println,
     task::{console, executor::Executor, keyboard, Task},
+    time,
 };
-use x86_64::VirtAddr;
-
-pub const VIRTUAL_OFFSET: usize = 0xF0000000;
+use x86_64::{PhysAddr, VirtAddr};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
@@ -40,17 +43,32 @@ impl AcpiHandler for TableHandler {
         physical_address: usize,
         size: usize,
     ) -> acpi::PhysicalMapping<Self, T> {
+        // ACPI tables live in ordinary RAM, so they can be mapped cacheable
+        let region = map_mmio(
+            PhysAddr::new(physical_address as u64),
+            size,
+            CacheMode::WriteBack,
+        )
+        .expect("failed to map ACPI table");
+        let mapped_size = region.mapped_size();
+        let virt = region.leak();
         PhysicalMapping::<Self, T>::new(
             physical_address,
-            NonNull::<T>::new((VIRTUAL_OFFSET + physical_address) as *mut T).unwrap(),
-            size,
+            NonNull::<T>::new(virt.as_mut_ptr()).unwrap(),
             size,
+            mapped_size,
             *self,
         )
     }
 
-    fn unmap_physical_region<T>(_region: &acpi::PhysicalMapping<Self, T>) {
-        return;
+    fn unmap_physical_region<T>(region: &acpi::PhysicalMapping<Self, T>) {
+        drop(unsafe {
+            MmioRegion::from_raw(
+                PhysAddr::new(region.physical_start() as u64),
+                VirtAddr::from_ptr(region.virtual_start().as_ptr()),
+                region.region_length(),
+            )
+        });
     }
 }
 
@@ -65,10 +83,19 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 
 pub static BOOTLOADER_CFG: bootloader_api::BootloaderConfig = {
     let mut config = bootloader_api::BootloaderConfig::new_default();
-    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::FixedAddress(
-        VIRTUAL_OFFSET as u64,
-    )); //TODO make this accessible to other modules
-        // config.kernel_stack_size = 100 * 1024;
+    #[cfg(not(feature = "kaslr"))]
+    {
+        config.mappings.physical_memory = Some(bootloader_api::config::Mapping::FixedAddress(
+            memory::PHYS_MEMORY_BASE,
+        ));
+    }
+    // Let the bootloader randomize the kernel image and all of its dynamic mappings
+    #[cfg(feature = "kaslr")]
+    {
+        config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+        config.mappings.aslr = true;
+    }
+    // config.kernel_stack_size = 100 * 1024;
     config
 };
 
@@ -78,20 +105,28 @@ fn kmain(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     // Create a BootInfo pointer for the init function to use
     let bi_ptr: *mut BootInfo = &mut *boot_info;
 
-    // ACPI parser
-    let rdsp_addr = &boot_info.rsdp_addr.into_option().unwrap();
-    let acpi = unsafe { AcpiTables::from_rsdp(TableHandler {}, *rdsp_addr as usize).unwrap() };
+    // Paging and the heap come first, everything after relies on them for mappings
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.take().unwrap());
+    unsafe { memory::init(phys_mem_offset, &boot_info.memory_regions) };
+    unsafe {
+        memory::protect::remap_kernel(
+            boot_info.kernel_addr,
+            boot_info.kernel_len,
+            boot_info.kernel_image_offset,
+        )
+    };
+    allocator::init_heap().expect("heap initialization failed");
 
     // Init kernel
     kernel::init(unsafe { &mut *bi_ptr });
 
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.take().unwrap());
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_alloc = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
-    allocator::init_heap(&mut mapper, &mut frame_alloc).expect("heap initialization failed");
+    // ACPI parser
+    let rdsp_addr = &boot_info.rsdp_addr.into_option().unwrap();
+    let acpi = unsafe { AcpiTables::from_rsdp(TableHandler {}, *rdsp_addr as usize).unwrap() };
 
     // TODO DSDT AML Parser
 
+    // Device interrupts are routed according to the MADT, nothing arrives before this
     match acpi.platform_info().unwrap().interrupt_model {
         acpi::InterruptModel::Unknown => {}
         acpi::InterruptModel::Apic(apic) => {
@@ -100,10 +135,18 @@ fn kmain(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
             for ioapic in apic.io_apics.iter() {
                 println!("[APIC] {:?}", ioapic);
             }
+            interrupts::init_ioapics(&apic.io_apics, &apic.interrupt_source_overrides);
         }
         _ => {}
     }
 
+    // Prefer the HPET for calibrating the LAPIC timer, fall back to the PIT without one
+    if let Err(err) = time::hpet::init(&acpi) {
+        println!("[HPET] not available: {:?}", err);
+    }
+    time::init();
+    time::rtc::init(true);
+
     // test_write();
 
     println!("Welcome to gertrudOS!");
@@ -117,7 +160,8 @@ fn kmain(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
 // handles panic (duh)
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    // dump the info to serial for now
+    // dump the info to serial and the screen
+    serial_println!("{}", info);
     println!("{}", info);
     kernel::hlt_loop();
 }
@@ -1,86 +0,0 @@
-use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
-use x86_64::{
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
-    PhysAddr, VirtAddr,
-};
-
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryRegions,
-    next: usize,
-}
-
-impl BootInfoFrameAllocator {
-    pub unsafe fn init(mmap: &'static MemoryRegions) -> Self {
-        BootInfoFrameAllocator {
-            memory_map: mmap,
-            next: 0,
-        }
-    }
-
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
-        let addr_ranges = usable.map(|r| r.start..r.end);
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
-}
-
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
-}
-
-pub unsafe fn init(phys_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    let l4_table = active_level_4_table(phys_memory_offset);
-    OffsetPageTable::new(l4_table, phys_memory_offset)
-}
-
-unsafe fn active_level_4_table(phys_memory_offset: VirtAddr) -> &'static mut PageTable {
-    use x86_64::registers::control::Cr3;
-
-    let (level_4_table_frame, _) = Cr3::read();
-
-    let phys = level_4_table_frame.start_address();
-    let virt = phys_memory_offset + phys.as_u64();
-    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
-
-    &mut *page_table_ptr
-}
-
-pub unsafe fn translate_addr(addr: VirtAddr, phys_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    _translate_addr(addr, phys_memory_offset)
-}
-
-fn _translate_addr(addr: VirtAddr, phys_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    use x86_64::{registers::control::Cr3, structures::paging::page_table::FrameError};
-
-    let (l4_table_frame, _) = Cr3::read();
-
-    let table_indexes = [
-        addr.p4_index(),
-        addr.p3_index(),
-        addr.p2_index(),
-        addr.p1_index(),
-    ];
-    let mut frame = l4_table_frame;
-
-    for &idx in &table_indexes {
-        let virt = phys_memory_offset + frame.start_address().as_u64();
-        let table_ptr: *const PageTable = virt.as_ptr();
-        let table = unsafe { &*table_ptr };
-
-        let entry = &table[idx];
-        frame = match entry.frame() {
-            Ok(frame) => frame,
-            Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported yet"),
-        };
-    }
-
-    Some(frame.start_address() + u64::from(addr.page_offset()))
-}
@@ -0,0 +1,281 @@
+use super::{BitmapFrameAllocator, FRAME_ALLOCATOR, FRAME_SIZE};
+use spin::{mutex::Mutex, once::Once};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Largest block the allocator hands out: 2^18 frames = 1 GiB.
+pub const MAX_ORDER: usize = 18;
+/// Smallest block the pool grows by, so small allocations don't scatter it over memory.
+const MIN_GROW_ORDER: usize = 9;
+
+const NONE: u64 = u64::MAX;
+const NOT_FREE: u8 = u8::MAX;
+
+pub static BUDDY_ALLOCATOR: Once<Mutex<BuddyFrameAllocator>> = Once::new();
+
+/// Intrusive list node written into the first bytes of every free block.
+#[derive(Clone, Copy)]
+struct FreeBlock {
+    next: u64,
+    prev: u64,
+}
+
+/// Buddy-system allocator for physically contiguous, naturally aligned power-of-two runs of
+/// frames. The pool starts out empty and takes aligned blocks from the [`BitmapFrameAllocator`]
+/// whenever no free block is large enough, so it only ever holds what was asked of it.
+pub struct BuddyFrameAllocator {
+    phys_memory_offset: VirtAddr,
+    /// End of the physical memory covered by `block_orders`.
+    end: u64,
+    free_lists: [u64; MAX_ORDER + 1],
+    /// Order of the free block starting at each frame of physical memory, [`NOT_FREE`] if none.
+    block_orders: &'static mut [u8],
+    total_frames: usize,
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Sets up the bookkeeping for all of the memory `frame_alloc` manages, with an empty pool.
+    ///
+    /// # Safety
+    ///
+    /// All of physical memory must be mapped at `phys_memory_offset`.
+    pub unsafe fn init(
+        frame_alloc: &mut BitmapFrameAllocator,
+        phys_memory_offset: VirtAddr,
+    ) -> Option<Self> {
+        let frames = frame_alloc.frame_count();
+        let meta_frames = frames.div_ceil(FRAME_SIZE as usize);
+        let meta = frame_alloc.allocate_contiguous(meta_frames, 1)?;
+        let meta_ptr: *mut u8 = (phys_memory_offset + meta.start_address().as_u64()).as_mut_ptr();
+        let block_orders = core::slice::from_raw_parts_mut(meta_ptr, frames);
+        block_orders.fill(NOT_FREE);
+
+        Some(BuddyFrameAllocator {
+            phys_memory_offset,
+            end: frames as u64 * FRAME_SIZE,
+            free_lists: [NONE; MAX_ORDER + 1],
+            block_orders,
+            total_frames: 0,
+            free_frames: 0,
+        })
+    }
+
+    /// Moves a block of at least `2^order` frames from `frame_alloc` into the pool. Prefers
+    /// 2 MiB blocks for smaller orders and falls back to the exact order if memory is too
+    /// fragmented for that.
+    pub fn grow(&mut self, frame_alloc: &mut BitmapFrameAllocator, order: usize) -> bool {
+        if order > MAX_ORDER {
+            return false;
+        }
+        let preferred = order.max(MIN_GROW_ORDER);
+        let fallback = (preferred != order).then_some(order);
+        for order in core::iter::once(preferred).chain(fallback) {
+            let frames = 1 << order;
+            if let Some(block) = frame_alloc.allocate_contiguous(frames, frames) {
+                self.total_frames += frames;
+                self.insert(block.start_address().as_u64(), order);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Smallest order whose block holds at least `size` bytes.
+    pub fn order_for(size: u64) -> usize {
+        let frames = size.div_ceil(FRAME_SIZE).max(1);
+        frames.next_power_of_two().ilog2() as usize
+    }
+
+    /// Allocates a block of `2^order` contiguous frames aligned to its own size, growing the
+    /// pool if necessary.
+    ///
+    /// Growing takes the [`FRAME_ALLOCATOR`] lock, which therefore must not be held.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysAddr> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        let find = |this: &Self| (order..=MAX_ORDER).find(|&o| this.free_lists[o] != NONE);
+        let mut current = match find(self) {
+            Some(current) => current,
+            None => {
+                let mut frame_alloc = FRAME_ALLOCATOR.get()?.lock();
+                if !self.grow(&mut frame_alloc, order) {
+                    return None;
+                }
+                find(self)?
+            }
+        };
+        let addr = self.free_lists[current];
+        self.remove_free(addr, current);
+
+        while current > order {
+            current -= 1;
+            self.push_free(addr + block_size(current), current);
+        }
+
+        self.free_frames -= 1 << order;
+        Some(PhysAddr::new(addr))
+    }
+
+    /// Returns a block to the pool, merging it with its buddy as long as that is free too.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must have been returned by [`Self::allocate`] with the same `order` and must no
+    /// longer be in use.
+    pub unsafe fn deallocate(&mut self, addr: PhysAddr, order: usize) {
+        let addr = addr.as_u64();
+        assert!(
+            order <= MAX_ORDER && addr + block_size(order) <= self.end,
+            "freeing block outside of physical memory"
+        );
+        assert!(
+            !self.is_free(addr, order),
+            "double free of block {:#x}",
+            addr
+        );
+        self.insert(addr, order);
+    }
+
+    /// Whether the block at `addr` is free already, either as a block of its own or as part of
+    /// a larger one.
+    fn is_free(&self, addr: u64, order: usize) -> bool {
+        (order..=MAX_ORDER).any(|o| {
+            let head = addr & !(block_size(o) - 1);
+            match self.block_orders[self.index(head)] {
+                NOT_FREE => false,
+                head_order => head + block_size(head_order as usize) > addr,
+            }
+        })
+    }
+
+    /// Adds a free block to the pool, merging it with its buddy as long as that is free too.
+    fn insert(&mut self, mut addr: u64, mut order: usize) {
+        self.free_frames += 1 << order;
+
+        while order < MAX_ORDER {
+            let buddy = addr ^ block_size(order);
+            if buddy + block_size(order) > self.end
+                || self.block_orders[self.index(buddy)] != order as u8
+            {
+                break;
+            }
+            self.remove_free(buddy, order);
+            addr = addr.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(addr, order);
+    }
+
+    /// Number of frames the pool took from the bitmap allocator so far.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Number of frames in the pool that are currently free.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    fn index(&self, addr: u64) -> usize {
+        (addr / FRAME_SIZE) as usize
+    }
+
+    fn node(&self, addr: u64) -> &'static mut FreeBlock {
+        let ptr: *mut FreeBlock = (self.phys_memory_offset + addr).as_mut_ptr();
+        unsafe { &mut *ptr }
+    }
+
+    fn push_free(&mut self, addr: u64, order: usize) {
+        let head = self.free_lists[order];
+        *self.node(addr) = FreeBlock {
+            next: head,
+            prev: NONE,
+        };
+        if head != NONE {
+            self.node(head).prev = addr;
+        }
+        self.free_lists[order] = addr;
+        let idx = self.index(addr);
+        self.block_orders[idx] = order as u8;
+    }
+
+    fn remove_free(&mut self, addr: u64, order: usize) {
+        let FreeBlock { next, prev } = *self.node(addr);
+        if prev == NONE {
+            self.free_lists[order] = next;
+        } else {
+            self.node(prev).next = next;
+        }
+        if next != NONE {
+            self.node(next).prev = prev;
+        }
+        let idx = self.index(addr);
+        self.block_orders[idx] = NOT_FREE;
+    }
+}
+
+/// Sets up the buddy allocator and makes it available through [`BUDDY_ALLOCATOR`].
+///
+/// # Safety
+///
+/// All of physical memory must be mapped at `phys_memory_offset`. Must only be called once.
+pub unsafe fn init(frame_alloc: &mut BitmapFrameAllocator, phys_memory_offset: VirtAddr) {
+    let buddy = BuddyFrameAllocator::init(frame_alloc, phys_memory_offset)
+        .expect("failed to allocate buddy allocator metadata");
+    BUDDY_ALLOCATOR.call_once(|| Mutex::new(buddy));
+}
+
+fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+fn order_of<S: PageSize>() -> usize {
+    (S::SIZE / FRAME_SIZE).ilog2() as usize
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let addr = self.allocate(order_of::<Size4KiB>())?;
+        Some(PhysFrame::containing_address(addr))
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let addr = self.allocate(order_of::<Size2MiB>())?;
+        Some(PhysFrame::containing_address(addr))
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        let addr = self.allocate(order_of::<Size1GiB>())?;
+        Some(PhysFrame::containing_address(addr))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.deallocate(frame.start_address(), order_of::<Size4KiB>());
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.deallocate(frame.start_address(), order_of::<Size2MiB>());
+    }
+}
+
+impl FrameDeallocator<Size1GiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size1GiB>) {
+        self.deallocate(frame.start_address(), order_of::<Size1GiB>());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::serial_println;
+use spin::once::Once;
+use x86_64::{structures::paging::PageTable, VirtAddr};
+
+/// Where the kernel address window starts when it isn't randomized.
+pub const DEFAULT_KERNEL_VIRT_START: u64 = 0x4444_0000_0000;
+/// Size of the window that stacks, heaps and MMIO mappings are carved from.
+pub const KERNEL_VIRT_SIZE: u64 = 0x100_0000_0000;
+
+static LAYOUT: Once<Layout> = Once::new();
+
+/// Placement of the kernel's virtual memory areas for the current boot.
+///
+/// With the `kaslr` feature the bootloader picks a random physical memory offset and the kernel
+/// address window is slid to a random free spot, so nothing may hardcode these addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    /// Virtual address at which all of physical memory is mapped.
+    pub phys_memory_offset: VirtAddr,
+    /// Start of the kernel address window managed by [`super::vmm`].
+    pub kernel_virt_start: VirtAddr,
+    pub kernel_virt_size: u64,
+    /// Whether the kernel window was placed at a random address.
+    pub randomized: bool,
+}
+
+impl Layout {
+    pub fn kernel_virt_end(&self) -> VirtAddr {
+        self.kernel_virt_start + self.kernel_virt_size
+    }
+
+    pub fn in_kernel_virt(&self, addr: VirtAddr) -> bool {
+        addr >= self.kernel_virt_start && addr < self.kernel_virt_end()
+    }
+}
+
+/// Decides the layout for this boot. `l4_table` is the active level 4 table, the kernel window
+/// is only placed where it doesn't overlap anything the bootloader mapped.
+pub(super) fn init(phys_memory_offset: VirtAddr, l4_table: &PageTable) -> &'static Layout {
+    LAYOUT.call_once(|| {
+        let (kernel_virt_start, randomized) = kernel_virt_start(l4_table);
+        let layout = Layout {
+            phys_memory_offset,
+            kernel_virt_start: VirtAddr::new(kernel_virt_start),
+            kernel_virt_size: KERNEL_VIRT_SIZE,
+            randomized,
+        };
+        serial_println!(
+            "[MEM] physical memory at {:?}, kernel window {:?}-{:?}",
+            layout.phys_memory_offset,
+            layout.kernel_virt_start,
+            layout.kernel_virt_end()
+        );
+        layout
+    })
+}
+
+/// Start of the kernel window and whether it was randomized.
+#[cfg(not(feature = "kaslr"))]
+fn kernel_virt_start(_l4_table: &PageTable) -> (u64, bool) {
+    (DEFAULT_KERNEL_VIRT_START, false)
+}
+
+/// Picks a random page aligned start in the lower half whose window only covers unused level 4
+/// entries. Entry 0 is skipped, it holds the low identity mappings of the bootloader.
+#[cfg(feature = "kaslr")]
+fn kernel_virt_start(l4_table: &PageTable) -> (u64, bool) {
+    use crate::cpu;
+    use x86_64::structures::paging::{PageSize, Size4KiB};
+
+    const L4_ENTRY_SPAN: u64 = 512 * 1024 * 1024 * 1024;
+    // One spare entry so the start can also be slid within the first entry
+    let entries = KERNEL_VIRT_SIZE.div_ceil(L4_ENTRY_SPAN) as usize + 1;
+    let fits = |idx: &usize| (*idx..*idx + entries).all(|i| l4_table[i].is_unused());
+    let candidates = || (1..=256 - entries).filter(fits);
+
+    let count = candidates().count() as u64;
+    if count == 0 {
+        serial_println!("[MEM] no free space for a randomized kernel window");
+        return (DEFAULT_KERNEL_VIRT_START, false);
+    }
+    let idx = candidates()
+        .nth((cpu::random_u64() % count) as usize)
+        .unwrap() as u64;
+    let slide = cpu::random_u64() % (L4_ENTRY_SPAN / Size4KiB::SIZE) * Size4KiB::SIZE;
+    (idx * L4_ENTRY_SPAN + slide, true)
+}
+
+/// The layout chosen at boot.
+pub fn get() -> &'static Layout {
+    LAYOUT.get().expect("memory not initialized")
+}
+
+/// Like [`get`], for code that may run before memory is initialized, e.g. fault handlers.
+pub fn try_get() -> Option<&'static Layout> {
+    LAYOUT.get()
+}
@@ -0,0 +1,161 @@
+use super::{
+    vmm::{self, VmmError},
+    FRAME_ALLOCATOR, MAPPER,
+};
+use x86_64::{
+    structures::paging::{
+        frame::PhysFrameRangeInclusive, mapper::MapToError, Mapper, Page, PageSize, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Memory type of a mapping, selected through the PWT/PCD bits with the default PAT layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    UncachedMinus,
+    Uncached,
+}
+
+impl CacheMode {
+    fn flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            CacheMode::UncachedMinus => PageTableFlags::NO_CACHE,
+            CacheMode::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// A physical range mapped into kernel address space. The mapping is removed again on drop.
+#[derive(Debug)]
+pub struct MmioRegion {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    size: usize,
+}
+
+impl MmioRegion {
+    /// Rebuilds a region from the parts of a previously [leaked](Self::leak) one.
+    ///
+    /// # Safety
+    ///
+    /// `phys`, `virt` and `size` must describe a mapping created by [`map_mmio`] that is not
+    /// owned by any other `MmioRegion`.
+    pub unsafe fn from_raw(phys: PhysAddr, virt: VirtAddr, size: usize) -> Self {
+        MmioRegion { phys, virt, size }
+    }
+
+    /// Physical address the region starts at.
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Virtual address that corresponds to [`Self::phys_addr`].
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of bytes actually mapped, i.e. the region rounded out to whole pages.
+    pub fn mapped_size(&self) -> usize {
+        let first = self.virt.align_down(Size4KiB::SIZE);
+        let end = (self.virt + self.size as u64).align_up(Size4KiB::SIZE);
+        (end - first) as usize
+    }
+
+    pub fn as_ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(offset + core::mem::size_of::<T>() <= self.size);
+        (self.virt + offset as u64).as_mut_ptr()
+    }
+
+    /// # Safety
+    ///
+    /// Reading the register at `offset` must not have side effects the caller isn't prepared for.
+    pub unsafe fn read<T: Copy>(&self, offset: usize) -> T {
+        self.as_ptr::<T>(offset).read_volatile()
+    }
+
+    /// # Safety
+    ///
+    /// Writing `value` to the register at `offset` must be valid for the device.
+    pub unsafe fn write<T: Copy>(&self, offset: usize, value: T) {
+        self.as_ptr::<T>(offset).write_volatile(value)
+    }
+
+    /// Keeps the mapping alive forever and returns its virtual address.
+    pub fn leak(self) -> VirtAddr {
+        let virt = self.virt;
+        core::mem::forget(self);
+        virt
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        let start = self.virt.align_down(Size4KiB::SIZE);
+        let size = self.mapped_size() as u64;
+        unsafe { vmm::unmap_pages(start, size, false) };
+        vmm::free_range(start, size);
+    }
+}
+
+/// Maps `frames` to consecutive pages starting at `base`.
+fn map_frames(
+    base: VirtAddr,
+    frames: PhysFrameRangeInclusive,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MAPPER.get().expect("memory not initialized").lock();
+    let mut frame_allocator = FRAME_ALLOCATOR
+        .get()
+        .expect("memory not initialized")
+        .lock();
+    for (idx, frame) in frames.enumerate() {
+        let page = Page::containing_address(base + idx as u64 * Size4KiB::SIZE);
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, &mut *frame_allocator)?
+                .flush()
+        };
+    }
+    Ok(())
+}
+
+/// Maps `size` bytes of device memory at `phys` into kernel address space.
+pub fn map_mmio(
+    phys: PhysAddr,
+    size: usize,
+    cache_mode: CacheMode,
+) -> Result<MmioRegion, VmmError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(phys + (size.max(1) as u64 - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let mapped_size = frames.count() as u64 * Size4KiB::SIZE;
+
+    let base = vmm::allocate_range(mapped_size)?;
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache_mode.flags();
+    if let Err(err) = map_frames(base, frames, flags) {
+        // Undo the part that was mapped already, the frames belong to the device
+        unsafe { vmm::unmap_pages(base, mapped_size, false) };
+        vmm::free_range(base, mapped_size);
+        return Err(err.into());
+    }
+
+    let offset = phys.as_u64() - first_frame.start_address().as_u64();
+    Ok(MmioRegion {
+        phys,
+        virt: base + offset,
+        size,
+    })
+}
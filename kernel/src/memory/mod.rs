@@ -0,0 +1,397 @@
+pub mod buddy;
+pub mod layout;
+pub mod mmio;
+pub mod protect;
+pub mod vmm;
+
+use crate::println;
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::fmt;
+use spin::{mutex::Mutex, once::Once};
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, OffsetPageTable, PageSize, PageTable, PageTableFlags,
+        PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Virtual address the bootloader is asked to map all of physical memory at without KASLR.
+pub const PHYS_MEMORY_BASE: u64 = 0xF0000000;
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+pub static MAPPER: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
+pub static FRAME_ALLOCATOR: Once<Mutex<BitmapFrameAllocator>> = Once::new();
+
+/// Physical frame allocator that tracks every 4 KiB frame with a single bit (set = in use).
+///
+/// The bitmap itself lives in the first usable region large enough to hold it and is
+/// accessed through the physical memory mapping, so it works before the heap exists.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize,
+    total_frames: usize,
+    free_frames: usize,
+    next_word: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Builds the allocator from the bootloader memory map.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that `mmap` is valid, that all of physical memory is mapped
+    /// at `phys_memory_offset` and that frames marked as usable are not used by anything else.
+    /// Must only be called once.
+    pub unsafe fn init(mmap: &'static MemoryRegions, phys_memory_offset: VirtAddr) -> Self {
+        let usable = || mmap.iter().filter(|r| r.kind == MemoryRegionKind::Usable);
+
+        let max_addr = usable().map(|r| r.end).max().unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_size = (words * core::mem::size_of::<u64>()) as u64;
+
+        let bitmap_start = usable()
+            .map(|r| (align_up(r.start, FRAME_SIZE), r.end))
+            .find(|&(start, end)| start + bitmap_size <= end)
+            .map(|(start, _)| start)
+            .expect("no usable region large enough for the frame bitmap");
+
+        let bitmap_ptr: *mut u64 = (phys_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = core::slice::from_raw_parts_mut(bitmap_ptr, words);
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            frame_count,
+            total_frames: 0,
+            free_frames: 0,
+            next_word: 0,
+        };
+
+        for region in usable() {
+            let start = align_up(region.start, FRAME_SIZE) / FRAME_SIZE;
+            let end = region.end / FRAME_SIZE;
+            for idx in start..end {
+                allocator.set_free(idx as usize);
+            }
+            allocator.total_frames += end.saturating_sub(start) as usize;
+        }
+
+        let bitmap_frames = bitmap_size.div_ceil(FRAME_SIZE);
+        for idx in 0..bitmap_frames {
+            allocator.set_used((bitmap_start / FRAME_SIZE + idx) as usize);
+        }
+
+        allocator
+    }
+
+    /// Number of frames up to the end of the highest usable region, usable or not.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Number of usable frames reported by the bootloader.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Number of frames that are currently available for allocation.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of usable frames that are currently handed out (including the bitmap itself).
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// Allocates `count` physically contiguous frames, the first of which is aligned to
+    /// `align` frames. This is a linear scan and meant for rare, large allocations like growing
+    /// the buddy pool.
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrame> {
+        let align = align.max(1);
+        let mut start = 0;
+        while start + count <= self.frame_count {
+            match (start..start + count).find(|&idx| self.is_used(idx)) {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    for idx in start..start + count {
+                        self.set_used(idx);
+                    }
+                    return Some(PhysFrame::containing_address(PhysAddr::new(
+                        start as u64 * FRAME_SIZE,
+                    )));
+                }
+            }
+        }
+        None
+    }
+
+    fn is_used(&self, idx: usize) -> bool {
+        self.bitmap[idx / BITS_PER_WORD] & (1 << (idx % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, idx: usize) {
+        if !self.is_used(idx) {
+            self.bitmap[idx / BITS_PER_WORD] |= 1 << (idx % BITS_PER_WORD);
+            self.free_frames -= 1;
+        }
+    }
+
+    fn set_free(&mut self, idx: usize) {
+        if self.is_used(idx) {
+            self.bitmap[idx / BITS_PER_WORD] &= !(1 << (idx % BITS_PER_WORD));
+            self.free_frames += 1;
+        }
+    }
+
+    fn find_free(&self) -> Option<usize> {
+        let words = self.bitmap.len();
+        (self.next_word..words)
+            .chain(0..self.next_word)
+            .find(|&word| self.bitmap[word] != u64::MAX)
+            .map(|word| word * BITS_PER_WORD + self.bitmap[word].trailing_ones() as usize)
+            .filter(|&idx| idx < self.frame_count)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let idx = self.find_free()?;
+        self.set_used(idx);
+        self.next_word = idx / BITS_PER_WORD;
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            idx as u64 * FRAME_SIZE,
+        )))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let idx = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(
+            idx < self.frame_count,
+            "freeing frame outside of usable memory"
+        );
+        assert!(self.is_used(idx), "double free of {:?}", frame);
+        self.set_free(idx);
+        self.next_word = self.next_word.min(idx / BITS_PER_WORD);
+    }
+}
+
+fn align_up(addr: u64, align: u64) -> u64 {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Sets up the global page table mapper and the physical frame allocators.
+///
+/// # Safety
+///
+/// All of physical memory must be mapped at `phys_memory_offset` and `mmap` must be the
+/// bootloader's memory map. Must only be called once.
+pub unsafe fn init(phys_memory_offset: VirtAddr, mmap: &'static MemoryRegions) {
+    protect::enable();
+    let l4_table = active_level_4_table(phys_memory_offset);
+    let layout = layout::init(phys_memory_offset, l4_table);
+    vmm::init(layout.kernel_virt_start, layout.kernel_virt_size);
+    MAPPER.call_once(|| Mutex::new(OffsetPageTable::new(l4_table, phys_memory_offset)));
+
+    let mut frame_alloc = BitmapFrameAllocator::init(mmap, phys_memory_offset);
+    buddy::init(&mut frame_alloc, phys_memory_offset);
+    FRAME_ALLOCATOR.call_once(|| Mutex::new(frame_alloc));
+}
+
+/// Virtual address at which the bootloader mapped all of physical memory.
+pub fn phys_memory_offset() -> VirtAddr {
+    layout::get().phys_memory_offset
+}
+
+unsafe fn active_level_4_table(phys_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = phys_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+pub unsafe fn translate_addr(addr: VirtAddr, phys_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    _translate_addr(addr, phys_memory_offset)
+}
+
+fn _translate_addr(addr: VirtAddr, phys_memory_offset: VirtAddr) -> Option<PhysAddr> {
+    use x86_64::{registers::control::Cr3, structures::paging::page_table::FrameError};
+
+    let (l4_table_frame, _) = Cr3::read();
+
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame = l4_table_frame;
+
+    for (level, &idx) in table_indexes.iter().enumerate() {
+        let virt = phys_memory_offset + frame.start_address().as_u64();
+        let table_ptr: *const PageTable = virt.as_ptr();
+        let table = unsafe { &*table_ptr };
+
+        let entry = &table[idx];
+        frame = match entry.frame() {
+            Ok(frame) => frame,
+            Err(FrameError::FrameNotPresent) => return None,
+            Err(FrameError::HugeFrame) => {
+                // A huge entry in the P3 table maps 1 GiB, one in the P2 table 2 MiB. In the P1
+                // table the bit is PAT and the entry an ordinary 4 KiB leaf. In huge entries bit
+                // 12 is PAT too, so the base has to be masked to the page size.
+                let page_size = match level {
+                    1 => Size1GiB::SIZE,
+                    2 => Size2MiB::SIZE,
+                    3 => Size4KiB::SIZE,
+                    _ => return None,
+                };
+                let base = entry.addr().as_u64() & !(page_size - 1);
+                return Some(PhysAddr::new(base) + (addr.as_u64() & (page_size - 1)));
+            }
+        };
+    }
+
+    Some(frame.start_address() + u64::from(addr.page_offset()))
+}
+
+/// A present leaf mapping. `flags` are the effective flags: writable and user accessible only
+/// if every level allows it, non-executable if any level forbids execution.
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+}
+
+/// Calls `f` for every present leaf mapping of the active page tables in ascending order.
+pub fn walk_page_tables(mut f: impl FnMut(Mapping)) {
+    use x86_64::registers::control::Cr3;
+
+    let (l4_table_frame, _) = Cr3::read();
+    let inherited = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    walk_table(l4_table_frame.start_address(), 4, 0, inherited, &mut f);
+}
+
+fn walk_table(
+    table_addr: PhysAddr,
+    level: u32,
+    base: u64,
+    inherited: PageTableFlags,
+    f: &mut dyn FnMut(Mapping),
+) {
+    let table_ptr: *const PageTable = (phys_memory_offset() + table_addr.as_u64()).as_ptr();
+    let table = unsafe { &*table_ptr };
+    let shift = 12 + 9 * (level - 1);
+
+    for (idx, entry) in table.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let virt = base | ((idx as u64) << shift);
+        let mut flags = entry.flags();
+        flags.remove(!inherited & (PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE));
+        flags.insert(inherited & PageTableFlags::NO_EXECUTE);
+
+        let is_leaf = level == 1 || (level < 4 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if is_leaf {
+            let size = 1 << shift;
+            f(Mapping {
+                virt: VirtAddr::new_truncate(virt),
+                // Bit 12 of a huge entry is PAT, not part of the address
+                phys: PhysAddr::new(entry.addr().as_u64() & !(size - 1)),
+                size,
+                flags,
+            });
+        } else {
+            walk_table(entry.addr(), level - 1, virt, flags, f);
+        }
+    }
+}
+
+/// Prints all present mappings. Runs of equally sized pages that are contiguous both virtually
+/// and physically and share the same flags are merged into a single line.
+pub fn dump_page_tables() {
+    print_mappings(|_| true);
+}
+
+/// Prints the mappings `filter` selects, merged like in [`dump_page_tables`], and returns the
+/// number of bytes they cover.
+fn print_mappings(filter: impl Fn(&Mapping) -> bool) -> u64 {
+    let mut run: Option<(Mapping, u64)> = None;
+    let mut total = 0;
+    walk_page_tables(|mapping| {
+        if !filter(&mapping) {
+            return;
+        }
+        total += mapping.size;
+        if let Some((first, len)) = &mut run {
+            let continues = first.size == mapping.size
+                && first.flags == mapping.flags
+                && first.virt.as_u64() + *len == mapping.virt.as_u64()
+                && first.phys.as_u64() + *len == mapping.phys.as_u64();
+            if continues {
+                *len += mapping.size;
+                return;
+            }
+            print_mapping_run(first, *len);
+        }
+        run = Some((mapping, mapping.size));
+    });
+
+    if let Some((first, len)) = run {
+        print_mapping_run(&first, len);
+    }
+    total
+}
+
+fn print_mapping_run(first: &Mapping, len: u64) {
+    let size = if first.size == Size1GiB::SIZE {
+        "1G"
+    } else if first.size == Size2MiB::SIZE {
+        "2M"
+    } else {
+        "4K"
+    };
+    println!(
+        "{:#018x}-{:#018x} -> {:#014x} {:>6} x {} {}",
+        first.virt.as_u64(),
+        first.virt.as_u64() + len,
+        first.phys.as_u64(),
+        len / first.size,
+        size,
+        FlagsDisplay(first.flags)
+    );
+}
+
+/// Compact `WUXGCT`-style rendering of the interesting page table flags.
+struct FlagsDisplay(PageTableFlags);
+
+impl fmt::Display for FlagsDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}{}{}",
+            flag(self.0.contains(PageTableFlags::WRITABLE), 'W'),
+            flag(self.0.contains(PageTableFlags::USER_ACCESSIBLE), 'U'),
+            flag(!self.0.contains(PageTableFlags::NO_EXECUTE), 'X'),
+            flag(self.0.contains(PageTableFlags::GLOBAL), 'G'),
+            flag(self.0.contains(PageTableFlags::NO_CACHE), 'C'),
+            flag(self.0.contains(PageTableFlags::WRITE_THROUGH), 'T'),
+        )
+    }
+}
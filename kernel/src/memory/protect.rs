@@ -0,0 +1,86 @@
+use super::{phys_memory_offset, print_mappings, Mapping, MAPPER};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        mapper::TranslateResult, Mapper, Page, PageTableFlags, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+/// Makes the CPU honour `NO_EXECUTE` and read-only mappings, including for ring 0 writes.
+pub fn enable() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+/// Remaps every loadable segment of the kernel image with exactly the permissions its ELF
+/// program header asks for: text read-only and executable, everything else non-executable.
+///
+/// # Safety
+///
+/// `kernel_addr` and `kernel_len` must describe the kernel ELF file in physical memory and
+/// `image_offset` the offset it was loaded at, as reported by the bootloader.
+pub unsafe fn remap_kernel(kernel_addr: u64, kernel_len: u64, image_offset: u64) {
+    let elf = core::slice::from_raw_parts(
+        (phys_memory_offset() + kernel_addr).as_ptr::<u8>(),
+        kernel_len as usize,
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes(elf[at..at + 2].try_into().unwrap());
+    let read_u32 = |at: usize| u32::from_le_bytes(elf[at..at + 4].try_into().unwrap());
+    let read_u64 = |at: usize| u64::from_le_bytes(elf[at..at + 8].try_into().unwrap());
+
+    assert_eq!(&elf[..4], b"\x7fELF", "kernel image is not an ELF file");
+    let ph_offset = read_u64(0x20) as usize;
+    let ph_size = read_u16(0x36) as usize;
+    let ph_count = read_u16(0x38) as usize;
+
+    let mut mapper = MAPPER.get().expect("memory not initialized").lock();
+    for header in (0..ph_count).map(|idx| ph_offset + idx * ph_size) {
+        if read_u32(header) != PT_LOAD {
+            continue;
+        }
+        let flags = read_u32(header + 0x04);
+        let vaddr = image_offset + read_u64(header + 0x10);
+        let mem_size = read_u64(header + 0x28);
+        if mem_size == 0 {
+            continue;
+        }
+
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(vaddr));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(vaddr + mem_size - 1));
+        for page in Page::range_inclusive(first, last) {
+            let TranslateResult::Mapped {
+                flags: mut page_flags,
+                ..
+            } = mapper.translate(page.start_address())
+            else {
+                continue;
+            };
+            page_flags.set(PageTableFlags::WRITABLE, flags & PF_W != 0);
+            page_flags.set(PageTableFlags::NO_EXECUTE, flags & PF_X == 0);
+            if let Ok(flush) = mapper.update_flags(page, page_flags) {
+                flush.flush();
+            }
+        }
+    }
+}
+
+fn is_wx(mapping: &Mapping) -> bool {
+    mapping.flags.contains(PageTableFlags::WRITABLE)
+        && !mapping.flags.contains(PageTableFlags::NO_EXECUTE)
+}
+
+/// Prints every mapping that is both writable and executable and returns how many bytes
+/// they cover. Anything reported here breaks W^X.
+pub fn audit_wx() -> u64 {
+    print_mappings(is_wx)
+}
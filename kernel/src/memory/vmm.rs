@@ -0,0 +1,411 @@
+use super::{layout, phys_memory_offset, FRAME_ALLOCATOR, MAPPER};
+use spin::mutex::Mutex;
+use x86_64::{
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize,
+            PageTableFlags, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+const MAX_FREE_RANGES: usize = 128;
+const MAX_LAZY_REGIONS: usize = 32;
+
+/// Free space of the kernel address window, empty until [`init`] placed the window.
+static KERNEL_VIRT: Mutex<VirtualRangeAllocator> = Mutex::new(VirtualRangeAllocator::empty());
+
+/// Lazily backed regions that the page fault handler fills in on first access.
+static LAZY_REGIONS: Mutex<[Option<LazyRegion>; MAX_LAZY_REGIONS]> =
+    Mutex::new([None; MAX_LAZY_REGIONS]);
+
+#[derive(Debug)]
+pub enum VmmError {
+    OutOfVirtualSpace,
+    TooManyLazyRegions,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for VmmError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        VmmError::Map(err)
+    }
+}
+
+/// Whether a region gets physical frames right away, only once they are needed or only when
+/// its owner maps them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    Eager,
+    /// Faulted in on first access. Touching a page fails fatally if no frame is left or the
+    /// faulting code holds the paging locks, so only for users that can live with that.
+    Lazy,
+    /// Only reserves the address space, the owner maps pages itself with [`map_pages`].
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LazyRegion {
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FreeRange {
+    start: u64,
+    end: u64,
+}
+
+/// First-fit allocator over a sorted, fixed-size list of free ranges, so it works before the
+/// heap exists.
+struct VirtualRangeAllocator {
+    free: [FreeRange; MAX_FREE_RANGES],
+    len: usize,
+}
+
+impl VirtualRangeAllocator {
+    const fn empty() -> Self {
+        VirtualRangeAllocator {
+            free: [FreeRange { start: 0, end: 0 }; MAX_FREE_RANGES],
+            len: 0,
+        }
+    }
+
+    const fn new(start: u64, end: u64) -> Self {
+        let mut free = [FreeRange { start: 0, end: 0 }; MAX_FREE_RANGES];
+        free[0] = FreeRange { start, end };
+        VirtualRangeAllocator { free, len: 1 }
+    }
+
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let idx = (0..self.len).find(|&i| self.free[i].end - self.free[i].start >= size)?;
+        let start = self.free[idx].start;
+        self.free[idx].start += size;
+        if self.free[idx].start == self.free[idx].end {
+            self.free.copy_within(idx + 1..self.len, idx);
+            self.len -= 1;
+        }
+        Some(start)
+    }
+
+    fn free(&mut self, start: u64, size: u64) {
+        let end = start + size;
+        let idx = (0..self.len)
+            .find(|&i| self.free[i].start >= end)
+            .unwrap_or(self.len);
+
+        let merges_prev = idx > 0 && self.free[idx - 1].end == start;
+        let merges_next = idx < self.len && self.free[idx].start == end;
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.free[idx - 1].end = self.free[idx].end;
+                self.free.copy_within(idx + 1..self.len, idx);
+                self.len -= 1;
+            }
+            (true, false) => self.free[idx - 1].end = end,
+            (false, true) => self.free[idx].start = start,
+            (false, false) => {
+                // Dropping the range leaks address space, which is preferable to panicking
+                if self.len == MAX_FREE_RANGES {
+                    return;
+                }
+                self.free.copy_within(idx..self.len, idx + 1);
+                self.free[idx] = FreeRange { start, end };
+                self.len += 1;
+            }
+        }
+    }
+}
+
+/// Hands the kernel address window chosen by [`layout`] to the range allocator.
+pub(super) fn init(start: VirtAddr, size: u64) {
+    *KERNEL_VIRT.lock() = VirtualRangeAllocator::new(start.as_u64(), (start + size).as_u64());
+}
+
+/// A range of kernel virtual memory, optionally surrounded by unmapped guard pages.
+#[derive(Debug)]
+pub struct VirtualRegion {
+    start: VirtAddr,
+    size: u64,
+    guard_pages: u64,
+    backing: Backing,
+}
+
+impl VirtualRegion {
+    /// First usable address, right after the lower guard pages.
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// One past the last usable address.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn backing(&self) -> Backing {
+        self.backing
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start() && addr < self.end()
+    }
+}
+
+/// Reserves `size` bytes of kernel address space with `guard_pages` unmapped pages on either
+/// side. Eagerly backed regions are mapped with `flags` right away, lazy ones get zeroed frames
+/// mapped with `flags` by the page fault handler as they are touched and manual ones are left
+/// unmapped.
+pub fn allocate_region(
+    size: u64,
+    guard_pages: u64,
+    backing: Backing,
+    flags: PageTableFlags,
+) -> Result<VirtualRegion, VmmError> {
+    let size = size.next_multiple_of(PAGE_SIZE);
+    let guard_size = guard_pages * PAGE_SIZE;
+    let base = KERNEL_VIRT
+        .lock()
+        .allocate(size + 2 * guard_size)
+        .ok_or(VmmError::OutOfVirtualSpace)?;
+
+    let region = VirtualRegion {
+        start: VirtAddr::new(base + guard_size),
+        size,
+        guard_pages,
+        backing,
+    };
+    match backing {
+        Backing::Eager => {
+            if let Err(err) = map_pages(region.start(), size, flags) {
+                unsafe { free_region(region) };
+                return Err(err.into());
+            }
+        }
+        Backing::Lazy => {
+            let lazy = LazyRegion {
+                start: region.start().as_u64(),
+                end: region.end().as_u64(),
+                flags,
+            };
+            let mut lazy_regions = LAZY_REGIONS.lock();
+            match lazy_regions.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(lazy),
+                None => {
+                    drop(lazy_regions);
+                    unsafe { free_region(region) };
+                    return Err(VmmError::TooManyLazyRegions);
+                }
+            }
+        }
+        Backing::Manual => {}
+    }
+    Ok(region)
+}
+
+/// Unmaps everything mapped inside `region`, frees the backing frames and returns the address
+/// space to the allocator.
+///
+/// # Safety
+///
+/// Nothing may reference the region anymore. Frames mapped into it must have come from the
+/// global frame allocator.
+pub unsafe fn free_region(region: VirtualRegion) {
+    if region.backing == Backing::Lazy {
+        let start = region.start.as_u64();
+        for slot in LAZY_REGIONS.lock().iter_mut() {
+            if slot.is_some_and(|lazy| lazy.start == start) {
+                *slot = None;
+            }
+        }
+    }
+    unmap_pages(region.start(), region.size(), true);
+    let guard_size = region.guard_pages * PAGE_SIZE;
+    KERNEL_VIRT.lock().free(
+        region.start.as_u64() - guard_size,
+        region.size + 2 * guard_size,
+    );
+}
+
+/// Reserves address space without any guard pages or backing, e.g. for device mappings.
+pub(super) fn allocate_range(size: u64) -> Result<VirtAddr, VmmError> {
+    KERNEL_VIRT
+        .lock()
+        .allocate(size.next_multiple_of(PAGE_SIZE))
+        .map(VirtAddr::new)
+        .ok_or(VmmError::OutOfVirtualSpace)
+}
+
+/// Returns address space obtained from [`allocate_range`].
+pub(super) fn free_range(start: VirtAddr, size: u64) {
+    KERNEL_VIRT
+        .lock()
+        .free(start.as_u64(), size.next_multiple_of(PAGE_SIZE));
+}
+
+fn page_range(start: VirtAddr, size: u64) -> impl Iterator<Item = Page> {
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(start + (size.max(1) - 1));
+    Page::range_inclusive(first, last)
+}
+
+/// Maps fresh frames for every page in `start..start + size`.
+///
+/// Takes the global mapper and frame allocator locks, so it must not be called while either of
+/// them is held.
+pub fn map_pages(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = MAPPER.get().expect("memory not initialized").lock();
+    let mut frame_allocator = FRAME_ALLOCATOR
+        .get()
+        .expect("memory not initialized")
+        .lock();
+
+    for (idx, page) in page_range(start, size).enumerate() {
+        let mapped = match frame_allocator.allocate_frame() {
+            Some(frame) => {
+                let mapped = unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) };
+                if mapped.is_err() {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                mapped.map(|flush| flush.flush())
+            }
+            None => Err(MapToError::FrameAllocationFailed),
+        };
+        if let Err(err) = mapped {
+            // Roll back the pages mapped so far so the caller can simply retry later
+            drop((mapper, frame_allocator));
+            if idx > 0 {
+                let first = start.align_down(PAGE_SIZE);
+                unsafe { unmap_pages(first, idx as u64 * PAGE_SIZE, true) };
+            }
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps every mapped page in `start..start + size`, handing the frames back to the global
+/// frame allocator if `free_frames` is set.
+///
+/// # Safety
+///
+/// Nothing may reference the range anymore.
+pub unsafe fn unmap_pages(start: VirtAddr, size: u64, free_frames: bool) {
+    let mut mapper = MAPPER.get().expect("memory not initialized").lock();
+    let mut frame_allocator = FRAME_ALLOCATOR
+        .get()
+        .expect("memory not initialized")
+        .lock();
+
+    for page in page_range(start, size) {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            if free_frames {
+                frame_allocator.deallocate_frame(frame);
+            }
+        }
+    }
+}
+
+/// Tries to resolve a page fault at `addr` by backing the faulting page of a lazy region with
+/// a zeroed frame. Returns `false` if the fault has to be treated as fatal.
+///
+/// Called from the page fault handler, so it only ever tries to take the locks it needs.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    // Only not-present faults can be fixed up, everything else is a genuine access violation
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    let flags = match LAZY_REGIONS.try_lock() {
+        Some(lazy_regions) => lazy_regions
+            .iter()
+            .flatten()
+            .find(|lazy| (lazy.start..lazy.end).contains(&addr.as_u64()))
+            .map(|lazy| lazy.flags),
+        None => None,
+    };
+    let Some(flags) = flags else {
+        return false;
+    };
+
+    let (Some(mut mapper), Some(mut frame_allocator)) = (
+        MAPPER.get().and_then(|m| m.try_lock()),
+        FRAME_ALLOCATOR.get().and_then(|f| f.try_lock()),
+    ) else {
+        return false;
+    };
+
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    let frame_ptr: *mut u8 = (phys_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { core::ptr::write_bytes(frame_ptr, 0, PAGE_SIZE as usize) };
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    match unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
+
+/// Describes what kind of kernel memory `addr` belongs to, for crash reports.
+pub fn describe_addr(addr: VirtAddr) -> &'static str {
+    let addr = addr.as_u64();
+    let in_lazy = LAZY_REGIONS.try_lock().is_some_and(|lazy_regions| {
+        lazy_regions
+            .iter()
+            .flatten()
+            .any(|lazy| (lazy.start..lazy.end).contains(&addr))
+    });
+
+    if in_lazy {
+        "lazily backed region"
+    } else if layout::try_get().is_some_and(|layout| layout.in_kernel_virt(VirtAddr::new(addr))) {
+        "kernel region allocator window (unmapped or guard page)"
+    } else {
+        "outside of any kernel region"
+    }
+}
+
+/// An eagerly mapped kernel stack with a guard page below and above it, so an overflow faults
+/// instead of silently corrupting neighbouring memory.
+#[derive(Debug)]
+pub struct KernelStack {
+    region: VirtualRegion,
+}
+
+impl KernelStack {
+    /// Initial stack pointer, stacks grow downwards from here.
+    pub fn top(&self) -> VirtAddr {
+        self.region.end()
+    }
+
+    pub fn bottom(&self) -> VirtAddr {
+        self.region.start()
+    }
+}
+
+pub fn allocate_stack(pages: u64) -> Result<KernelStack, VmmError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let region = allocate_region(pages * PAGE_SIZE, 1, Backing::Eager, flags)?;
+    Ok(KernelStack { region })
+}
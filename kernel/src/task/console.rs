@@ -1,8 +1,15 @@
+use crate::allocator::{self, stats::set_tracing};
 use crate::framebuffer::print_image;
+use crate::memory::{self, buddy::BUDDY_ALLOCATOR, FRAME_ALLOCATOR};
 use crate::{ata::pio::test_read, print};
 use crate::{clear, println};
-use alloc::string::String;
+use crate::{
+    cpu,
+    interrupts::{self, ioapic, stats},
+    time,
+};
 use alloc::vec::Vec;
+use alloc::{format, string::String};
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -148,15 +155,155 @@ pub async fn run_console() {
                         registers::debug::Dr7::read()
                     );
                 }
+                "pt" => {
+                    memory::dump_page_tables();
+                }
+                "wx" => {
+                    let bytes = memory::protect::audit_wx();
+                    if bytes == 0 {
+                        println!("No writable and executable mappings");
+                    } else {
+                        println!("{} KiB mapped writable and executable!", bytes / 1024);
+                    }
+                }
+                _ => {
+                    println!("Unknown debug target!\nUsage: dbg [all,rflags,cr,dr,pt,wx]");
+                }
+            },
+            "mem" => match args.next().unwrap_or("") {
+                "" => {
+                    let heap = allocator::stats();
+                    println!(
+                        "Heap: {} KiB mapped (max {} KiB), {} bytes in use, peak {} bytes",
+                        heap.heap_size / 1024,
+                        allocator::HEAP_MAX_SIZE / 1024,
+                        heap.in_use,
+                        heap.peak
+                    );
+                    println!(
+                        "Allocations: {} ({} freed, {} failed), largest free block {} bytes",
+                        heap.allocations, heap.deallocations, heap.failed, heap.largest_free_block
+                    );
+                    if let Some(frames) = FRAME_ALLOCATOR.get() {
+                        let frames = frames.lock();
+                        println!(
+                            "Frames: {} used, {} free, {} total",
+                            frames.used_frames(),
+                            frames.free_frames(),
+                            frames.total_frames()
+                        );
+                    }
+                    let layout = memory::layout::get();
+                    println!(
+                        "Layout: physical memory at {:?}, kernel window at {:?}, randomized: {}",
+                        layout.phys_memory_offset, layout.kernel_virt_start, layout.randomized
+                    );
+                    if let Some(buddy) = BUDDY_ALLOCATOR.get() {
+                        let buddy = buddy.lock();
+                        println!(
+                            "Buddy pool: {} of {} frames free",
+                            buddy.free_frames(),
+                            buddy.total_frames()
+                        );
+                    }
+                }
+                "trace" => match args.next().unwrap_or("") {
+                    "on" => set_tracing(true),
+                    "off" => set_tracing(false),
+                    _ => println!("Usage: mem trace [on,off]"),
+                },
                 _ => {
-                    println!("Unknown debug target!\nUsage: dbg [all,rflags,cr,dr]");
+                    println!("Unknown mem target!\nUsage: mem [trace]");
                 }
             },
+            "cpuinfo" => {
+                let info = cpu::info::get();
+                println!("Vendor: {}\nBrand: {}", info.vendor(), info.brand());
+                println!(
+                    "Family: {:#x}, model: {:#x}, stepping: {}",
+                    info.family, info.model, info.stepping
+                );
+                println!(
+                    "APIC id: {}, x2APIC id: {:?}, logical processors: {}",
+                    info.apic_id, info.x2apic_id, info.logical_processors
+                );
+                for cache in info.caches() {
+                    println!("{}", cache);
+                }
+                println!("Flags: {}", info.features);
+            }
+            "date" => {
+                let unix_time = time::rtc::unix_time();
+                println!(
+                    "{} ({}.{:03})",
+                    time::rtc::now(),
+                    unix_time.as_secs(),
+                    unix_time.subsec_millis()
+                );
+            }
+            "uptime" => {
+                let uptime = time::uptime();
+                let secs = uptime.as_secs();
+                println!(
+                    "Up {}:{:02}:{:02}.{:03} ({} ticks at {} Hz)",
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60,
+                    uptime.subsec_millis(),
+                    time::ticks(),
+                    time::TICK_HZ
+                );
+                println!(
+                    "TSC: {} MHz, used for the high resolution clock: {}",
+                    time::tsc::frequency() / 1_000_000,
+                    time::tsc::is_usable()
+                );
+            }
+            "interrupts" => {
+                let routes = ioapic::routes();
+                print!("Vector");
+                for cpu in 0..stats::cpus() {
+                    print!(" {:>10}", format!("CPU{}", cpu));
+                }
+                println!("  {:<24} {:>4}  IOAPIC", "Source", "GSI");
+                for vector in 0..=u8::MAX {
+                    let source = interrupts::vector_source(vector);
+                    let total = stats::total(vector);
+                    // Exceptions and unused vectors only show up once they were raised
+                    let wired = vector >= interrupts::INTERRUPT_BASE && source.is_some();
+                    if total == 0 && !wired {
+                        continue;
+                    }
+
+                    print!("{:>#6x}", vector);
+                    for cpu in 0..stats::cpus() {
+                        print!(" {:>10}", stats::count_on(vector, cpu));
+                    }
+                    print!("  {:<24}", source.unwrap_or("Unknown"));
+                    match routes.iter().find(|route| route.vector == vector) {
+                        Some(route) => {
+                            print!(" {:>4}", route.gsi);
+                            if let Some((id, pin)) = ioapic::gsi_location(route.gsi) {
+                                print!("  {}:{}", id, pin);
+                            }
+                            println!();
+                        }
+                        None => println!(" {:>4}", "-"),
+                    }
+                }
+                println!(
+                    "LAPIC errors: {} (last: {}, seen: {}), spurious: {}",
+                    interrupts::lapic::errors(),
+                    interrupts::lapic::last_error(),
+                    interrupts::lapic::all_errors(),
+                    interrupts::lapic::spurious()
+                );
+            }
             "clear" => {
                 clear!();
             }
             "help" => {
-                println!("clear - Clear the screen\ndbg - Print debug info\nhelp - Print this help message\nimage - Draw an image to screen\nqexit - Exit QEMU");
+                println!("clear - Clear the screen\ncpuinfo - Print CPU information\ndate - Print the current date and time\ndbg - Print debug info\nhelp - Print this help message\nimage - Draw an image to screen\ninterrupts - Print interrupt counts\nmem - Print memory usage\nqexit - Exit QEMU\nuptime - Print time since boot");
             }
             "qexit" => {
                 use x86_64::instructions::port::Port;
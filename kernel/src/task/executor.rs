@@ -1,4 +1,4 @@
-use super::{Task, TaskId};
+use super::{set_current_task, Task, TaskId};
 use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
@@ -77,7 +77,10 @@ impl Executor {
                 .entry(task_id)
                 .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
             let mut ctx = Context::from_waker(waker);
-            match task.poll(&mut ctx) {
+            set_current_task(Some(task_id));
+            let result = task.poll(&mut ctx);
+            set_current_task(None);
+            match result {
                 Poll::Ready(()) => {
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
@@ -7,6 +7,11 @@ pub mod console;
 pub mod executor;
 pub mod keyboard;
 pub mod simple_executor;
+pub mod time;
+
+const NO_TASK: u64 = u64::MAX;
+
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
@@ -23,6 +28,19 @@ impl TaskId {
     }
 }
 
+/// Id of the task that is currently being polled, if any.
+pub fn current_task_id() -> Option<u64> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(id),
+    }
+}
+
+fn set_current_task(task_id: Option<TaskId>) {
+    let id = task_id.map_or(NO_TASK, |id| id.0);
+    CURRENT_TASK.store(id, Ordering::Relaxed);
+}
+
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
         Task {
@@ -0,0 +1,256 @@
+use crate::time::{self, Instant};
+use alloc::vec::Vec;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use futures_util::stream::{Stream, StreamExt};
+use spin::mutex::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Number of slots in the timer wheel, timers further out than this wait for more rounds.
+const WHEEL_SLOTS: usize = 256;
+
+/// Pending timers, hashed by their deadline tick. Only ever locked with interrupts disabled
+/// outside of the timer interrupt, so the interrupt never spins on it.
+static WHEEL: Mutex<TimerWheel> = Mutex::new(TimerWheel::new());
+
+struct TimerEntry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+    woken: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TimerKey {
+    slot: usize,
+    id: u64,
+}
+
+struct TimerWheel {
+    slots: [Vec<TimerEntry>; WHEEL_SLOTS],
+    next_id: u64,
+    /// Last tick whose slot was processed.
+    processed: u64,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        TimerWheel {
+            slots: [const { Vec::new() }; WHEEL_SLOTS],
+            next_id: 0,
+            processed: 0,
+        }
+    }
+
+    fn insert(&mut self, deadline: u64, waker: Waker) -> TimerKey {
+        let key = TimerKey {
+            slot: deadline as usize % WHEEL_SLOTS,
+            id: self.next_id,
+        };
+        self.next_id += 1;
+        self.slots[key.slot].push(TimerEntry {
+            id: key.id,
+            deadline,
+            waker,
+            woken: false,
+        });
+        key
+    }
+
+    fn remove(&mut self, key: TimerKey) {
+        let slot = &mut self.slots[key.slot];
+        if let Some(idx) = slot.iter().position(|entry| entry.id == key.id) {
+            slot.swap_remove(idx);
+        }
+    }
+
+    fn update_waker(&mut self, key: TimerKey, waker: &Waker) {
+        let slot = &mut self.slots[key.slot];
+        if let Some(entry) = slot.iter_mut().find(|entry| entry.id == key.id) {
+            if !entry.waker.will_wake(waker) {
+                entry.waker = waker.clone();
+            }
+        }
+    }
+
+    /// Wakes every timer that expired up to `now`. Never allocates or drops a waker, which
+    /// makes it safe to call from the timer interrupt.
+    fn advance(&mut self, now: u64) {
+        // After a full round every slot has been looked at, no need to go further back
+        let first = (self.processed + 1).max(now.saturating_sub(WHEEL_SLOTS as u64 - 1));
+        for tick in first..=now {
+            for entry in self.slots[tick as usize % WHEEL_SLOTS].iter_mut() {
+                if !entry.woken && entry.deadline <= now {
+                    entry.woken = true;
+                    entry.waker.wake_by_ref();
+                }
+            }
+        }
+        self.processed = self.processed.max(now);
+    }
+}
+
+/// Called by the timer interrupt after the tick counter was advanced.
+pub(crate) fn on_tick() {
+    // Task code only holds the lock with interrupts disabled, so this can't fail on a single
+    // core. If it ever does, the skipped slots are caught up on the next tick.
+    if let Some(mut wheel) = WHEEL.try_lock() {
+        wheel.advance(time::ticks());
+    }
+}
+
+/// Future that completes once its deadline has passed, see [`sleep`].
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    key: Option<TimerKey>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Moves the deadline, which also makes a completed `Sleep` pending again.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        time::now() >= self.deadline
+    }
+
+    fn cancel(&mut self) {
+        if let Some(key) = self.key.take() {
+            without_interrupts(|| WHEEL.lock().remove(key));
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        // Checking the deadline and registering happen without a tick in between, so a
+        // timer can't expire unnoticed
+        without_interrupts(|| {
+            let mut wheel = WHEEL.lock();
+            if time::now() >= this.deadline {
+                if let Some(key) = this.key.take() {
+                    wheel.remove(key);
+                }
+                return Poll::Ready(());
+            }
+
+            match this.key {
+                Some(key) => wheel.update_waker(key, cx.waker()),
+                None => this.key = Some(wheel.insert(this.deadline.ticks(), cx.waker().clone())),
+            }
+            Poll::Pending
+        })
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Waits until `duration` has passed. The resolution is one timer tick.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::now() + duration)
+}
+
+/// Waits until `deadline` has been reached.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        key: None,
+    }
+}
+
+/// Returned by [`timeout`] when the deadline passed before the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Future returned by [`timeout`].
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // `future` is never moved out of the pinned `Timeout` and `Sleep` is `Unpin`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
+
+/// Runs `future` for at most `duration`, yielding [`Elapsed`] if it didn't finish in time.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// Stream that yields once every period, see [`interval`].
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+impl Interval {
+    /// Waits for the next tick and returns the instant it was scheduled for.
+    pub async fn tick(&mut self) -> Instant {
+        self.next().await.expect("interval streams never end")
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        let this = &mut *self;
+        if Pin::new(&mut this.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        // Ticks that were missed entirely are skipped instead of firing in a burst
+        let scheduled = this.sleep.deadline();
+        let mut next = scheduled + this.period;
+        if next <= time::now() {
+            next = time::now() + this.period;
+        }
+        this.sleep.reset(next);
+        Poll::Ready(Some(scheduled))
+    }
+}
+
+/// Yields immediately and then every `period`.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep_until(time::now()),
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    interrupts::{self, ioapic, InterruptIndex},
+    memory::{
+        mmio::{map_mmio, CacheMode, MmioRegion},
+        vmm::VmmError,
+    },
+    serial_println,
+};
+use acpi::{AcpiError, AcpiHandler, AcpiTables, HpetInfo};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use spin::once::Once;
+use x2apic::ioapic::IrqFlags;
+use x86_64::PhysAddr;
+
+const REGISTER_BLOCK_SIZE: usize = 0x400;
+
+const GENERAL_CAPABILITIES: usize = 0x000;
+const GENERAL_CONFIG: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0F0;
+const fn timer_config(timer: u8) -> usize {
+    0x100 + 0x20 * timer as usize
+}
+const fn timer_comparator(timer: u8) -> usize {
+    0x108 + 0x20 * timer as usize
+}
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+
+/// GSIs below this belong to ISA devices, even if they aren't routed yet.
+const FIRST_FREE_GSI: u32 = 16;
+/// Comparator driving the kernel tick, the only one raising [`InterruptIndex::Hpet`].
+const TICK_TIMER: u8 = 0;
+
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+static HPET: Once<Hpet> = Once::new();
+static INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+/// Set when the HPET instead of the LAPIC timer drives the kernel tick.
+static DRIVES_TICK: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum HpetError {
+    NotPresent(AcpiError),
+    Map(VmmError),
+    NoSuchTimer,
+    NotPeriodicCapable,
+    NoFreeRoute,
+    Route(ioapic::RoutingError),
+}
+
+/// The HPET register block described by the ACPI HPET table.
+#[derive(Debug)]
+pub struct Hpet {
+    regs: MmioRegion,
+    /// Length of one counter tick in femtoseconds.
+    period_fs: u64,
+    timers: u8,
+    counter_64bit: bool,
+}
+
+impl Hpet {
+    /// Main counter value. Wraps after 2^32 ticks on HPETs with a 32 bit counter.
+    pub fn counter(&self) -> u64 {
+        unsafe { self.regs.read::<u64>(MAIN_COUNTER) }
+    }
+
+    /// Counter ticks per second.
+    pub fn frequency(&self) -> u64 {
+        FEMTOS_PER_SEC / self.period_fs
+    }
+
+    /// Converts a difference of counter values to time.
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_nanos((ticks as u128 * self.period_fs as u128 / 1_000_000) as u64)
+    }
+
+    pub fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() * 1_000_000 / self.period_fs as u128).max(1) as u64
+    }
+
+    /// Time since the main counter was started.
+    pub fn elapsed(&self) -> Duration {
+        self.ticks_to_duration(self.counter())
+    }
+
+    pub fn timers(&self) -> u8 {
+        self.timers
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    /// Truncates a counter value to the width of the main counter.
+    fn wrap(&self, value: u64) -> u64 {
+        if self.counter_64bit {
+            value
+        } else {
+            value & u32::MAX as u64
+        }
+    }
+
+    /// Spins until `duration` has passed on the main counter.
+    pub fn busy_wait(&self, duration: Duration) {
+        let start = self.counter();
+        let ticks = self.duration_to_ticks(duration);
+        while self.wrap(self.counter().wrapping_sub(start)) < ticks {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Fires an interrupt every `period` from comparator `timer`.
+    pub fn start_periodic(&self, timer: u8, period: Duration) -> Result<(), HpetError> {
+        let config = self.read_timer_config(timer)?;
+        if config & TIMER_PERIODIC_CAPABLE == 0 {
+            return Err(HpetError::NotPeriodicCapable);
+        }
+        let route = self.route(config, vector(timer))?;
+        let ticks = self.duration_to_ticks(period);
+
+        // With VALUE_SET the first write sets the comparator, the second the period
+        let config = (config & !TIMER_ROUTE_MASK)
+            | (route << TIMER_ROUTE_SHIFT)
+            | TIMER_INT_ENABLE
+            | TIMER_PERIODIC
+            | TIMER_VALUE_SET;
+        unsafe {
+            self.regs.write(timer_config(timer), config);
+            self.regs.write(
+                timer_comparator(timer),
+                self.wrap(self.counter().wrapping_add(ticks)),
+            );
+            self.regs.write(timer_comparator(timer), ticks);
+        }
+        Ok(())
+    }
+
+    /// Fires a single interrupt from comparator `timer` once `delay` has passed.
+    pub fn start_oneshot(&self, timer: u8, delay: Duration) -> Result<(), HpetError> {
+        let config = self.read_timer_config(timer)?;
+        let route = self.route(config, vector(timer))?;
+        let deadline = self.wrap(self.counter().wrapping_add(self.duration_to_ticks(delay)));
+
+        let config = (config & !(TIMER_ROUTE_MASK | TIMER_PERIODIC))
+            | (route << TIMER_ROUTE_SHIFT)
+            | TIMER_INT_ENABLE;
+        unsafe {
+            self.regs.write(timer_config(timer), config);
+            self.regs.write(timer_comparator(timer), deadline);
+        }
+        Ok(())
+    }
+
+    /// Disables interrupts from comparator `timer`.
+    pub fn stop(&self, timer: u8) -> Result<(), HpetError> {
+        let config = self.read_timer_config(timer)?;
+        unsafe {
+            self.regs
+                .write(timer_config(timer), config & !TIMER_INT_ENABLE)
+        };
+        Ok(())
+    }
+
+    /// Replaces the LAPIC timer with comparator 0 as the source of the kernel tick.
+    pub fn drive_tick(&self) -> Result<(), HpetError> {
+        let period = Duration::from_nanos(1_000_000_000 / super::TICK_HZ);
+        self.start_periodic(TICK_TIMER, period)?;
+        DRIVES_TICK.store(true, Ordering::Relaxed);
+        unsafe { interrupts::LAPIC.lock().disable_timer() };
+        Ok(())
+    }
+
+    fn read_timer_config(&self, timer: u8) -> Result<u64, HpetError> {
+        if timer >= self.timers {
+            return Err(HpetError::NoSuchTimer);
+        }
+        Ok(unsafe { self.regs.read::<u64>(timer_config(timer)) })
+    }
+
+    /// Picks the highest free GSI the timer can be routed to and points it at `vector`. Keeps
+    /// the route the timer already has if it was set up before.
+    fn route(&self, config: u64, vector: u8) -> Result<u64, HpetError> {
+        let current = (config & TIMER_ROUTE_MASK) >> TIMER_ROUTE_SHIFT;
+        if config & TIMER_INT_ENABLE != 0 && current != 0 {
+            return Ok(current);
+        }
+
+        let capable = (config >> 32) as u32;
+        let gsi = (FIRST_FREE_GSI..32)
+            .rev()
+            .filter(|gsi| capable & (1 << gsi) != 0)
+            .find(|&gsi| ioapic::has_gsi(gsi) && !ioapic::is_routed(gsi))
+            .ok_or(HpetError::NoFreeRoute)?;
+        // The HPET signals IOAPIC inputs with active high, edge triggered interrupts
+        ioapic::route_gsi(gsi, vector, IrqFlags::empty()).map_err(HpetError::Route)?;
+        Ok(gsi as u64)
+    }
+}
+
+/// Vector raised by comparator `timer`. Only the tick comparator gets [`InterruptIndex::Hpet`],
+/// so an interrupt from any other one never advances the tick.
+fn vector(timer: u8) -> u8 {
+    if timer == TICK_TIMER {
+        InterruptIndex::Hpet.as_u8()
+    } else {
+        InterruptIndex::HpetTimer.as_u8()
+    }
+}
+
+/// Maps the HPET announced in the ACPI tables and starts its main counter.
+pub fn init<H: AcpiHandler>(tables: &AcpiTables<H>) -> Result<&'static Hpet, HpetError> {
+    let info = HpetInfo::new(tables).map_err(HpetError::NotPresent)?;
+    let regs = map_mmio(
+        PhysAddr::new(info.base_address as u64),
+        REGISTER_BLOCK_SIZE,
+        CacheMode::Uncached,
+    )
+    .map_err(HpetError::Map)?;
+
+    let capabilities = unsafe { regs.read::<u64>(GENERAL_CAPABILITIES) };
+    let hpet = Hpet {
+        regs,
+        period_fs: capabilities >> 32,
+        timers: ((capabilities >> 8) & 0x1F) as u8 + 1,
+        counter_64bit: capabilities & CAP_COUNTER_64BIT != 0,
+    };
+
+    unsafe {
+        // Legacy replacement routing stays off, interrupts go through the IOAPIC
+        hpet.regs.write::<u64>(GENERAL_CONFIG, CONFIG_ENABLE);
+    }
+    serial_println!(
+        "[HPET] {} timers, {} MHz, {} bit counter",
+        hpet.timers,
+        hpet.frequency() / 1_000_000,
+        if hpet.counter_64bit { 64 } else { 32 }
+    );
+    Ok(HPET.call_once(|| hpet))
+}
+
+/// The HPET, if [`init`] found one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+/// Number of HPET interrupts received so far.
+pub fn interrupts() -> u64 {
+    INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Called by the HPET interrupt handlers.
+pub(crate) fn on_interrupt() {
+    INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether the tick comparator replaced the LAPIC timer as the source of the kernel tick.
+pub(crate) fn drives_tick() -> bool {
+    DRIVES_TICK.load(Ordering::Relaxed)
+}
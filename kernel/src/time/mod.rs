@@ -0,0 +1,156 @@
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+use crate::{interrupts, serial_println};
+use core::{
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use x2apic::lapic::{TimerDivide, TimerMode};
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Frequency of the LAPIC timer interrupt.
+pub const TICK_HZ: u64 = 1000;
+
+/// Length of the window the LAPIC timer is measured over during calibration.
+const CALIBRATION_US: u64 = 10_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// LAPIC timer counts per second with the divider used by [`init`].
+static LAPIC_TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// A point in time measured in timer ticks since boot. Monotonic, never goes backwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant { ticks: ticks() }
+    }
+
+    pub fn from_ticks(ticks: u64) -> Self {
+        Instant { ticks }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Time passed between `earlier` and `self`, zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        ticks_to_duration(self.ticks.saturating_sub(earlier.ticks))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant {
+            ticks: self.ticks.saturating_add(duration_to_ticks(rhs)),
+        }
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Converts a number of ticks to the time they take.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(ticks.saturating_mul(1_000_000_000 / TICK_HZ))
+}
+
+/// Number of ticks that cover at least `duration`.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let nanos_per_tick = 1_000_000_000 / TICK_HZ as u128;
+    duration
+        .as_nanos()
+        .div_ceil(nanos_per_tick)
+        .min(u64::MAX as u128) as u64
+}
+
+/// Number of timer interrupts since [`init`].
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Current point on the monotonic clock.
+pub fn now() -> Instant {
+    Instant::now()
+}
+
+/// Time since the tick clock was started.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// LAPIC timer counts per second, 0 before calibration.
+pub fn lapic_timer_frequency() -> u64 {
+    LAPIC_TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Measures the LAPIC timer against the HPET, or the PIT if there is none, and programs it to
+/// fire [`TICK_HZ`] times a second.
+///
+/// Also determines the TSC frequency for [`tsc::Instant`].
+///
+/// Must run after the LAPIC was enabled and, to use the HPET, after [`hpet::init`].
+pub fn init() {
+    without_interrupts(|| {
+        calibrate_lapic_timer();
+        tsc::init();
+    });
+}
+
+fn calibrate_lapic_timer() {
+    let wait = || match hpet::get() {
+        Some(hpet) => hpet.busy_wait(Duration::from_micros(CALIBRATION_US)),
+        None => pit::busy_wait(CALIBRATION_US),
+    };
+
+    let mut lapic = interrupts::LAPIC.lock();
+    let counted = unsafe {
+        lapic.disable_timer();
+        lapic.set_timer_mode(TimerMode::OneShot);
+        lapic.set_timer_divide(TimerDivide::Div16);
+        lapic.set_timer_initial(u32::MAX);
+        wait();
+        let counted = u32::MAX - interrupts::lapic_timer_current();
+        lapic.set_timer_initial(0);
+        counted
+    };
+
+    let frequency = counted as u64 * 1_000_000 / CALIBRATION_US;
+    LAPIC_TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+    let initial = (frequency / TICK_HZ).clamp(1, u32::MAX as u64) as u32;
+    unsafe {
+        lapic.set_timer_mode(TimerMode::Periodic);
+        lapic.set_timer_initial(initial);
+        lapic.enable_timer();
+    }
+    serial_println!(
+        "[TIME] LAPIC timer runs at {} kHz, {} counts per tick, calibrated against the {}",
+        frequency / 1000,
+        initial,
+        if hpet::get().is_some() { "HPET" } else { "PIT" }
+    );
+}
@@ -0,0 +1,44 @@
+use x86_64::instructions::port::Port;
+
+/// Input clock of the PIT in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Keyboard controller port B, controls the channel 2 gate and exposes its output.
+const PORT_B: u16 = 0x61;
+
+const PORT_B_GATE: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT2: u8 = 1 << 5;
+
+/// Longest delay a single channel 2 countdown can measure.
+pub const MAX_WAIT_US: u64 = 0xFFFF * 1_000_000 / PIT_FREQUENCY;
+
+/// Busy waits for `us` microseconds, at most [`MAX_WAIT_US`], using PIT channel 2 in one-shot
+/// mode. Channel 2 isn't wired to an IRQ, so this works with interrupts disabled and doesn't
+/// disturb anything else.
+pub fn busy_wait(us: u64) {
+    assert!(us <= MAX_WAIT_US, "PIT wait of {} us is too long", us);
+    let count = (us * PIT_FREQUENCY / 1_000_000).max(1) as u16;
+
+    let mut port_b: Port<u8> = Port::new(PORT_B);
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel: Port<u8> = Port::new(CHANNEL_2);
+    unsafe {
+        // Keep the gate low while the count is loaded and the speaker off
+        let control = port_b.read() & !(PORT_B_GATE | PORT_B_SPEAKER);
+        port_b.write(control);
+
+        // Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+        command.write(0b1011_0000);
+        channel.write(count as u8);
+        channel.write((count >> 8) as u8);
+
+        port_b.write(control | PORT_B_GATE);
+        while port_b.read() & PORT_B_OUT2 == 0 {
+            core::hint::spin_loop();
+        }
+        port_b.write(control);
+    }
+}
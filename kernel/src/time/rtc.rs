@@ -0,0 +1,272 @@
+use crate::{
+    interrupts::{ioapic, InterruptIndex},
+    serial_println,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use spin::mutex::Mutex;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Keeps NMIs masked while a CMOS register is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_UPDATE_INT: u8 = 1 << 4;
+const STATUS_C_UPDATE_ENDED: u8 = 1 << 4;
+const HOUR_PM: u8 = 1 << 7;
+
+/// ISA IRQ of the RTC.
+const RTC_IRQ: u8 = 8;
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos::new());
+/// UNIX time in nanoseconds at uptime zero.
+static BOOT_TIME_NANOS: AtomicU64 = AtomicU64::new(0);
+static UPDATES: AtomicU64 = AtomicU64::new(0);
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    const fn new() -> Self {
+        Cmos {
+            index: Port::new(CMOS_INDEX),
+            data: Port::new(CMOS_DATA),
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        unsafe {
+            self.index.write(NMI_DISABLE | reg);
+            let value = self.data.read();
+            self.enable_nmi(reg);
+            value
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        unsafe {
+            self.index.write(NMI_DISABLE | reg);
+            self.data.write(value);
+            self.enable_nmi(reg);
+        }
+    }
+
+    /// The NMI mask lives in the index port, so it has to be cleared again after every access.
+    fn enable_nmi(&mut self, reg: u8) {
+        unsafe { self.index.write(reg) };
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_raw(&mut self) -> [u8; 6] {
+        [
+            self.read(REG_SECONDS),
+            self.read(REG_MINUTES),
+            self.read(REG_HOURS),
+            self.read(REG_DAY),
+            self.read(REG_MONTH),
+            self.read(REG_YEAR),
+        ]
+    }
+
+    /// Reads the clock, retrying until two reads outside of an update agree.
+    fn read_consistent(&mut self) -> DateTime {
+        let mut last = None;
+        loop {
+            while self.update_in_progress() {
+                core::hint::spin_loop();
+            }
+            let raw = self.read_raw();
+            if last == Some(raw) {
+                return self.decode(raw);
+            }
+            last = Some(raw);
+        }
+    }
+
+    /// Converts raw register values, which may be BCD and in 12 hour format.
+    fn decode(&mut self, raw: [u8; 6]) -> DateTime {
+        let status_b = self.read(REG_STATUS_B);
+        let binary = status_b & STATUS_B_BINARY != 0;
+        let value = |v: u8| {
+            if binary {
+                v
+            } else {
+                (v >> 4) * 10 + (v & 0x0F)
+            }
+        };
+
+        let [second, minute, hour, day, month, year] = raw;
+        let mut hour_24 = value(hour & !HOUR_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12 AM is hour 0, 12 PM stays hour 12
+            hour_24 %= 12;
+            if hour & HOUR_PM != 0 {
+                hour_24 += 12;
+            }
+        }
+
+        DateTime {
+            // The century register isn't standardized, assume the 21st
+            year: 2000 + value(year) as u16,
+            month: value(month),
+            day: value(day),
+            hour: hour_24,
+            minute: value(minute),
+            second: value(second),
+        }
+    }
+}
+
+/// A UTC calendar date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs_of_day = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        days as u64 * 86400 + secs_of_day
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs_of_day = secs % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Days since the UNIX epoch for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Anchors the wall clock to `date`, which was read just now.
+fn set_boot_time(date: DateTime) {
+    let unix_nanos = date.to_unix() as u128 * 1_000_000_000;
+    let nanos = unix_nanos.saturating_sub(super::uptime().as_nanos());
+    BOOT_TIME_NANOS.store(nanos as u64, Ordering::Relaxed);
+}
+
+/// Reads the current date and time straight from the RTC. Takes up to a few milliseconds if
+/// an update is in progress.
+pub fn read() -> DateTime {
+    without_interrupts(|| CMOS.lock().read_consistent())
+}
+
+/// Sets the wall clock from the RTC and, with `update_interrupt`, resynchronizes it on every
+/// RTC update through IRQ 8.
+pub fn init(update_interrupt: bool) {
+    set_boot_time(read());
+    if !update_interrupt {
+        return;
+    }
+
+    without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b | STATUS_B_UPDATE_INT);
+        // Reading status C acknowledges anything that is already pending
+        cmos.read(REG_STATUS_C);
+        if let Err(err) = ioapic::route_isa_irq(RTC_IRQ, InterruptIndex::CMOSClock.as_u8()) {
+            serial_println!("[RTC] failed to route IRQ {}: {:?}", RTC_IRQ, err);
+        }
+    });
+}
+
+/// Current wall clock time as the duration since the UNIX epoch.
+pub fn unix_time() -> Duration {
+    Duration::from_nanos(BOOT_TIME_NANOS.load(Ordering::Relaxed)) + super::uptime()
+}
+
+/// Current wall clock time as a calendar date.
+pub fn now() -> DateTime {
+    DateTime::from_unix(unix_time().as_secs())
+}
+
+/// Number of RTC update interrupts received so far.
+pub fn updates() -> u64 {
+    UPDATES.load(Ordering::Relaxed)
+}
+
+/// Called by the RTC interrupt handler.
+pub(crate) fn on_interrupt() {
+    // Task code only touches the CMOS with interrupts disabled
+    let Some(mut cmos) = CMOS.try_lock() else {
+        return;
+    };
+    // The RTC raises no further interrupts until status C was read
+    if cmos.read(REG_STATUS_C) & STATUS_C_UPDATE_ENDED != 0 {
+        UPDATES.fetch_add(1, Ordering::Relaxed);
+        // Right after an update the registers are stable for almost a second
+        let raw = cmos.read_raw();
+        let date = cmos.decode(raw);
+        set_boot_time(date);
+    }
+}
@@ -0,0 +1,162 @@
+use super::{hpet, pit};
+use crate::{
+    cpu::{
+        self, cpuid,
+        features::{self, Feature},
+    },
+    serial_println,
+};
+use core::{
+    ops::{Add, Sub},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+const CALIBRATION_US: u64 = 10_000;
+
+/// TSC ticks per second, 0 until [`init`] ran.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// TSC value at the moment the tick clock counted zero, lines both clocks up.
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// Whether the TSC runs at a constant rate, otherwise [`Instant`] uses the tick clock.
+static USABLE: AtomicBool = AtomicBool::new(false);
+
+/// Where the TSC frequency came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencySource {
+    Cpuid,
+    Hpet,
+    Pit,
+}
+
+/// A high resolution point in time, in nanoseconds since boot.
+///
+/// Backed by the TSC when it is invariant and by the much coarser LAPIC tick clock otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    nanos: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        let nanos = if USABLE.load(Ordering::Relaxed) {
+            cycles_to_nanos(cpu::rdtsc().saturating_sub(BASE.load(Ordering::Relaxed)))
+        } else {
+            super::uptime().as_nanos() as u64
+        };
+        Instant { nanos }
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.nanos
+    }
+
+    /// Time passed between `earlier` and `self`, zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.nanos.saturating_sub(earlier.nanos))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant {
+            nanos: self.nanos.saturating_add(rhs.as_nanos() as u64),
+        }
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+fn cycles_to_nanos(cycles: u64) -> u64 {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => 0,
+        frequency => (cycles as u128 * 1_000_000_000 / frequency as u128) as u64,
+    }
+}
+
+/// TSC ticks per second, 0 before [`init`].
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Whether [`Instant`] is backed by the TSC.
+pub fn is_usable() -> bool {
+    USABLE.load(Ordering::Relaxed)
+}
+
+/// Reads the TSC frequency from CPUID leaf 0x15, or failing that the nominal base frequency
+/// from leaf 0x16.
+fn frequency_from_cpuid() -> Option<u64> {
+    let max_leaf = cpuid(0, 0).eax;
+    if max_leaf >= 0x15 {
+        let leaf = cpuid(0x15, 0);
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+        }
+    }
+    if max_leaf >= 0x16 {
+        let base_mhz = cpuid(0x16, 0).eax & 0xFFFF;
+        if base_mhz != 0 {
+            return Some(base_mhz as u64 * 1_000_000);
+        }
+    }
+    None
+}
+
+/// Counts TSC ticks over a fixed window timed by the HPET or, without one, the PIT.
+fn calibrate() -> (u64, FrequencySource) {
+    let (start, end, source) = match hpet::get() {
+        Some(hpet) => {
+            let start = cpu::rdtsc();
+            hpet.busy_wait(Duration::from_micros(CALIBRATION_US));
+            (start, cpu::rdtsc(), FrequencySource::Hpet)
+        }
+        None => {
+            let start = cpu::rdtsc();
+            pit::busy_wait(CALIBRATION_US);
+            (start, cpu::rdtsc(), FrequencySource::Pit)
+        }
+    };
+    ((end - start) * 1_000_000 / CALIBRATION_US, source)
+}
+
+/// Determines the TSC frequency and decides whether [`Instant`] can use the TSC.
+///
+/// Should run with interrupts disabled so the calibration window isn't stretched.
+pub fn init() {
+    let (frequency, source) = match frequency_from_cpuid() {
+        Some(frequency) => (frequency, FrequencySource::Cpuid),
+        None => calibrate(),
+    };
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+
+    let invariant = features::has(Feature::InvariantTsc);
+    if features::has(Feature::Tsc) && invariant && frequency != 0 {
+        let tsc_since_boot = super::uptime().as_nanos() * frequency as u128 / 1_000_000_000;
+        BASE.store(
+            cpu::rdtsc().saturating_sub(tsc_since_boot as u64),
+            Ordering::Relaxed,
+        );
+        USABLE.store(true, Ordering::Relaxed);
+    }
+
+    serial_println!(
+        "[TSC] {} MHz from {:?}, invariant: {}, high resolution clock: {}",
+        frequency / 1_000_000,
+        source,
+        invariant,
+        if is_usable() { "TSC" } else { "tick clock" }
+    );
+}